@@ -0,0 +1,194 @@
+// Command line tool of operators controlling the running server through its admin socket:
+//     - each invocation sends one command and prints the response
+//     - exits with non-zero code if the server refuses the command or can not be reached
+use bytes::BytesMut;
+use rustenger_shared::{
+    account::{Presence, Username},
+    admin::{AdminClientCodec, AdminRequest, AdminResponse, RoomStats},
+    codec::{self, Format},
+    RoomName,
+};
+use std::{
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    process,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// size of chunks the response is read by
+const READ_BUFFER_SIZE: usize = 4096;
+
+fn main() {
+    let username = || {
+        clap::Arg::with_name("username")
+            .required(true)
+            .help("username of the account")
+    };
+    let matches = clap::App::new("Rustenger admin")
+        .version("0.0.0")
+        .author("Aitzhanov Ivan <aitvann@gmail.com>")
+        .about("Controls the running Rustenger server through its admin socket")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            clap::Arg::with_name("socket")
+                .short("s")
+                .long("socket")
+                .env("RUSTENGER_ADMIN")
+                .takes_value(true)
+                .required(true)
+                .help("path of the admin socket of the server"),
+        )
+        .subcommand(clap::SubCommand::with_name("rooms").about("lists rooms with their users"))
+        .subcommand(clap::SubCommand::with_name("users").about("lists users which are online"))
+        .subcommand(
+            clap::SubCommand::with_name("kick")
+                .about("disconnects the user, it can sign in again")
+                .arg(username()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("ban")
+                .about("disconnects the user and refuses its sign in until restart")
+                .arg(username()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("unban")
+                .about("allows the banned user to sign in again")
+                .arg(username()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("announce")
+                .about("sends the announcement to every signed in user")
+                .arg(
+                    clap::Arg::with_name("text")
+                        .required(true)
+                        .multiple(true)
+                        .help("text of the announcement"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("delete-room")
+                .about("deletes the room, its users are moved out of it")
+                .arg(
+                    clap::Arg::with_name("room")
+                        .required(true)
+                        .help("name of the room"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("reload")
+                .about("reloads the config of the server as on SIGHUP"),
+        )
+        .get_matches();
+
+    let request = request(&matches).unwrap_or_else(|e| fail(e));
+    let socket = matches.value_of("socket").expect("the socket is required");
+    let mut stream = UnixStream::connect(socket)
+        .unwrap_or_else(|e| fail(format!("failed to connect to {}: {}", socket, e)));
+
+    match exchange(&mut stream, request) {
+        Ok(AdminResponse::Error(e)) => fail(e),
+        Ok(response) => print(response),
+        Err(e) => fail(e),
+    }
+}
+
+/// prints the error and exits with non-zero code
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1)
+}
+
+/// builds the request from the subcommand and its arguments
+fn request(matches: &clap::ArgMatches) -> Result<AdminRequest, String> {
+    let username = |m: &clap::ArgMatches| {
+        let value = m.value_of("username").expect("the username is required");
+        Username::from(value).map_err(|e| format!("invalid username '{}': {}", value, e))
+    };
+
+    let request = match matches.subcommand() {
+        ("rooms", _) => AdminRequest::Rooms,
+        ("users", _) => AdminRequest::Users,
+        ("kick", Some(m)) => AdminRequest::Kick(username(m)?),
+        ("ban", Some(m)) => AdminRequest::Ban(username(m)?),
+        ("unban", Some(m)) => AdminRequest::Unban(username(m)?),
+        ("announce", Some(m)) => {
+            let words = m.values_of("text").expect("the text is required");
+            AdminRequest::Announce(words.collect::<Vec<_>>().join(" "))
+        }
+        ("delete-room", Some(m)) => {
+            let value = m.value_of("room").expect("the room is required");
+            let name = RoomName::from(value)
+                .map_err(|e| format!("invalid room name '{}': {}", value, e))?;
+            AdminRequest::DeleteRoom(name)
+        }
+        ("reload", _) => AdminRequest::Reload,
+        (cmd, _) => return Err(format!("unknown command: {}", cmd)),
+    };
+    Ok(request)
+}
+
+/// sends the request and waits for the response to it
+fn exchange(stream: &mut UnixStream, request: AdminRequest) -> Result<AdminResponse, codec::Error> {
+    let mut codec = AdminClientCodec::with_format(Format::Json);
+    let mut buf = BytesMut::new();
+    codec.encode(request, &mut buf)?;
+    stream.write_all(&buf)?;
+
+    buf.clear();
+    let mut chunk = [0; READ_BUFFER_SIZE];
+    loop {
+        if let Some(response) = codec.decode(&mut buf)? {
+            return Ok(response);
+        }
+
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            let msg = "the server has closed the connection";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg).into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// prints the successful response
+fn print(response: AdminResponse) {
+    match response {
+        AdminResponse::Rooms(rooms) if rooms.is_empty() => println!("there are no rooms"),
+        AdminResponse::Rooms(rooms) => rooms.iter().for_each(|r| println!("{}", room(r))),
+        AdminResponse::Users(users) if users.is_empty() => println!("nobody is online"),
+        AdminResponse::Users(users) => users.iter().for_each(|p| println!("{}", user(p))),
+        AdminResponse::Reloaded(changes) if changes.is_empty() => {
+            println!("config has not changed")
+        }
+        AdminResponse::Reloaded(changes) => changes.iter().for_each(|c| println!("{}", c)),
+        AdminResponse::Done => println!("done"),
+        AdminResponse::Error(_) => unreachable!("errors are reported by the caller"),
+    }
+}
+
+/// renders the room in one line with following format:
+///     [NAME] ([MESSAGES] messages): [USERNAME], ...
+fn room(room: &RoomStats) -> String {
+    let users: Vec<_> = room.users.iter().map(|u| u.to_string()).collect();
+    format!(
+        "{} ({} messages): {}",
+        room.name,
+        room.messages,
+        users.join(", ")
+    )
+}
+
+/// renders the user in one line with following format:
+///     [USERNAME] is [STATUS] in [ROOM]
+fn user(presence: &Presence) -> String {
+    let place = match presence.room {
+        Some(room) => format!("room '{}'", room),
+        None => "the lobby".into(),
+    };
+    format!(
+        "{} is {:?} in {}",
+        presence.account.username(),
+        presence.status,
+        place
+    )
+}
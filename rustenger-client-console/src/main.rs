@@ -1,14 +1,42 @@
-use rustenger_shared::codec::ClientCodec;
+use rustenger_shared::{
+    codec::{self, ClientCodec, Compression, Format, Preface},
+    message::{ClientMessage, Command, ServerMessage},
+    tls,
+};
 use std::{
+    io::{self, BufRead, Read, Write},
     iter,
     net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
+// blocking port of tokio-util framing
 mod framed;
 use framed::Framed;
 
+mod parse;
+mod render;
+
 const DEFAULT_ADDR: Ipv4Addr = Ipv4Addr::LOCALHOST;
 const DEFAULT_PORT: u16 = 4732;
+const DEFAULT_DOMAIN: &str = "localhost";
+
+/// how long reading from the server blocks before the input of the user is sent
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// how long the client may send nothing before it pings the server
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// how long the server may send nothing, it answers pings, before it is considered dead
+const SERVER_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// stream the client is connected through, e.g. plain TCP or TLS over TCP
+trait Stream: Read + Write {}
+
+impl<T> Stream for T where T: Read + Write {}
 
 fn main() {
     let matches = clap::App::new("Rustenger console client")
@@ -22,6 +50,38 @@ fn main() {
                 .takes_value(true)
                 .help("address of server"),
         )
+        .arg(
+            clap::Arg::with_name("ca")
+                .long("ca")
+                .takes_value(true)
+                .help("PEM file with trusted CA certificates, enables TLS"),
+        )
+        .arg(
+            clap::Arg::with_name("domain")
+                .long("domain")
+                .takes_value(true)
+                .requires("ca")
+                .help("domain name the server certificate is verified against"),
+        )
+        .arg(
+            clap::Arg::with_name("cert")
+                .long("cert")
+                .takes_value(true)
+                .requires_all(&["ca", "key"])
+                .help("PEM file with client certificate chain, used instead of password"),
+        )
+        .arg(
+            clap::Arg::with_name("key")
+                .long("key")
+                .takes_value(true)
+                .requires("cert")
+                .help("PEM file with client private key"),
+        )
+        .arg(
+            clap::Arg::with_name("compress")
+                .long("compress")
+                .help("compresses large frames with deflate"),
+        )
         .get_matches();
 
     let mut addrs = matches.values_of("addresses");
@@ -33,7 +93,114 @@ fn main() {
         .filter_map(|a: SocketAddr| TcpStream::connect(a).ok())
         .next()
         .expect("failed to connect to server");
+    stream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .expect("failed to set read timeout");
+
+    // TLS is enabled only if the CA certificates are given
+    let stream: Box<dyn Stream> = match matches.value_of("ca") {
+        Some(ca) => {
+            let ca = Path::new(ca);
+            let config = match (matches.value_of("cert"), matches.value_of("key")) {
+                (Some(cert), Some(key)) => {
+                    tls::client_config_with_cert(ca, Path::new(cert), Path::new(key))
+                }
+                _ => tls::client_config(ca),
+            }
+            .expect("failed to load TLS certificates");
+            let domain = matches.value_of("domain").unwrap_or(DEFAULT_DOMAIN);
+            let domain =
+                webpki::DNSNameRef::try_from_ascii_str(domain).expect("invalid domain name");
+            let session = rustls::ClientSession::new(&config, domain);
+            Box::new(rustls::StreamOwned::new(session, stream))
+        }
+        None => Box::new(stream),
+    };
+
+    let compression = if matches.is_present("compress") {
+        Compression::Deflate
+    } else {
+        Compression::None
+    };
+
+    run(stream, Preface::new(Format::Bincode, compression)).expect("connection error");
+}
+
+/// sends input of the user and prints messages from the server until the user exits
+fn run(mut stream: Box<dyn Stream>, preface: Preface) -> Result<(), codec::Error> {
+    stream.write_all(&preface.to_bytes())?;
+    let mut framed = Framed::new(stream, ClientCodec::with_preface(preface));
+
+    let (input_tx, input_rx) = mpsc::channel();
+    thread::spawn(move || read_input(input_tx));
+
+    let mut last_write = Instant::now();
+    let mut last_read = Instant::now();
+
+    loop {
+        // send all messages the user has entered
+        loop {
+            match input_rx.try_recv() {
+                Ok(msg) => {
+                    let exit = matches!(msg, ClientMessage::Command(Command::Exit));
+                    framed.send(msg)?;
+                    last_write = Instant::now();
+                    if exit {
+                        return Ok(());
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        // keep the connection alive while the user is silent
+        if last_write.elapsed() >= KEEPALIVE_INTERVAL {
+            framed.send(ClientMessage::Ping)?;
+            last_write = Instant::now();
+        }
+        if last_read.elapsed() >= SERVER_TIMEOUT {
+            let e = io::Error::new(io::ErrorKind::TimedOut, "server is not responding");
+            return Err(e.into());
+        }
+
+        match framed.read() {
+            Ok(msg) => {
+                last_read = Instant::now();
+                // direct messages are not acknowledged as delivered,
+                // the console has no keys, so the user can not read them
+                if !matches!(msg, ServerMessage::Pong) {
+                    println!("{}", render::server_message(&msg));
+                }
+            }
+            Err(codec::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// parses lines from stdin and passes them to 'tx', sends 'Exit' at the end of input
+fn read_input(tx: mpsc::Sender<ClientMessage>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        match parse::parse_input(&line) {
+            Ok(msg) => {
+                if tx.send(msg).is_err() {
+                    return;
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
 
-    let codec = ClientCodec::new();
-    let mut _framed = Framed::new(stream, codec);
+    tx.send(ClientMessage::Command(Command::Exit)).ok();
 }
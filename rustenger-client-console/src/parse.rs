@@ -1,7 +1,7 @@
 use arrayvec::ArrayString;
 use rustenger_shared::{
-    account::Color,
-    message::{ClientMessage, Command, UserMessage},
+    account::{Color, Password, Status, StatusMessage, Username},
+    message::{ClientMessage, Command, MessageId, Reaction, UserMessage},
     RoomName,
 };
 use std::str::FromStr;
@@ -9,20 +9,49 @@ use thiserror::Error;
 
 /// parse input with following format:
 ///     * [TEXT] = UserMessage
+///     * :r [MESSAGE ID] [TEXT] or ::Reply [MESSAGE ID] [TEXT] = UserMessage replying to the message
 ///     * :[COMMAND SHORT NAME] [ARG..] = Command -- one character, may be not all commands are avaliabel
 ///     * ::[COMMAND FULL NAME] [ARG..] = Command -- muiltiple character, all commands are avaliable
 pub fn parse_input(buffer: &str) -> Result<ClientMessage, Error> {
-    let client_message = if buffer.starts_with(":") {
-        let cmd = parse_command(&buffer[1..])?;
+    let client_message = if let Some(reply) = strip_reply(buffer) {
+        let (parent, msg) = parse_reply(reply)?;
+        ClientMessage::UserMessage(Box::new(msg), Some(parent))
+    } else if let Some(cmd) = buffer.strip_prefix(':') {
+        let cmd = parse_command(cmd)?;
         ClientMessage::Command(cmd)
     } else {
         let msg = parse_user_message(buffer)?;
-        ClientMessage::UserMessage(msg)
+        ClientMessage::UserMessage(Box::new(msg), None)
     };
 
     Ok(client_message)
 }
 
+/// returns the arguments of reply if 'buffer' is reply
+fn strip_reply(buffer: &str) -> Option<&str> {
+    [":r ", "::Reply "]
+        .iter()
+        .find(|p| buffer.starts_with(*p))
+        .map(|p| &buffer[p.len()..])
+}
+
+/// parse id of replied message and text of reply
+fn parse_reply(buffer: &str) -> Result<(MessageId, UserMessage), Error> {
+    let (id, text) = match buffer.find(' ') {
+        Some(pos) => (&buffer[..pos], &buffer[pos + 1..]),
+        None => {
+            return Err(Error::InvalidArgumentNum {
+                expected: 2,
+                found: 1,
+            })
+        }
+    };
+
+    let id = id.parse().map_err(|e| Error::Parse(Box::new(e)))?;
+    let msg = parse_user_message(text)?;
+    Ok((id, msg))
+}
+
 /// parse user message
 fn parse_user_message(buffer: &str) -> Result<UserMessage, Error> {
     let text = ArrayString::from(buffer).unwrap();
@@ -78,11 +107,25 @@ fn parse_command(buffer: &str) -> Result<Command, Error> {
     };
 
     let cmd = match cmd_name {
+        ":LogIn" => parse_args!(args => LogIn: Username, Password),
+        ":SignUp" => parse_args!(args => SignUp: Username, Password),
         "c" | ":CreateRoom" => parse_args!(args => CreateRoom: RoomName),
         "s" | ":SelectRoom" => parse_args!(args => SelectRoom: RoomName),
         "e" | ":ExitRoom" => parse_args!(args => ExitRoom),
         "l" | ":RoomsList" => parse_args!(args => RoomsList),
         ":SelectColor" => parse_args!(args => SelectColor: Color),
+        "t" | ":Thread" => parse_args!(args => Thread: MessageId),
+        "+" | ":React" => parse_args!(args => React: MessageId, Reaction),
+        "-" | ":Unreact" => parse_args!(args => Unreact: MessageId, Reaction),
+        "m" | ":Mentions" => parse_args!(args => Mentions),
+        "a" | ":SetStatus" => parse_status(args)?,
+        "w" | ":WhoIs" => parse_args!(args => WhoIs: Username),
+        "o" | ":WhoIsOnline" => parse_args!(args => WhoIsOnline: u32),
+        "k" | ":FetchKeys" => parse_args!(args => FetchKeys: Username),
+        "i" | ":Directs" => parse_args!(args => Directs),
+        "u" | ":MarkRead" => parse_args!(args => MarkRead: RoomName, MessageId),
+        ":MarkDirectRead" => parse_args!(args => MarkDirectRead: MessageId),
+        ":Receipts" => parse_args!(args => Receipts),
         "d" | ":DeleteAccount" => parse_args!(args => DeleteAccount),
         "q" | ":Quit" => parse_args!(args => Exit), // TODO: rename in the server
         _ => return Err(Error::InvalidCommandName),
@@ -91,6 +134,18 @@ fn parse_command(buffer: &str) -> Result<Command, Error> {
     Ok(cmd)
 }
 
+/// parse status and optional status message which may contain spaces
+fn parse_status(buffer: &str) -> Result<Command, Error> {
+    let (status, message) = match buffer.find(' ') {
+        Some(pos) => (&buffer[..pos], &buffer[pos + 1..]),
+        None => (buffer, ""),
+    };
+
+    let status = Status::from_str(status).map_err(|e| Error::Parse(Box::new(e)))?;
+    let message = StatusMessage::from(message).map_err(|e| Error::Parse(Box::new(e.simplify())))?;
+    Ok(Command::SetStatus(status, message))
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("parse error: {0}")]
@@ -0,0 +1,195 @@
+use chrono::Local;
+use rustenger_shared::{
+    account::{Presence, Username},
+    e2e::KeyBundle,
+    message::{
+        AccountMessage, DirectMessage, DirectStatus, Event, Mention, MessageId, OnlinePage,
+        Receipt, Response, RoomInfo, ServerMessage,
+    },
+};
+use std::{collections::HashMap, fmt::Write};
+
+/// width of one level of replies indentation
+const INDENT: usize = 4;
+
+/// renders the message from the server, direct messages are rendered encrypted
+pub fn server_message(msg: &ServerMessage) -> String {
+    match msg {
+        ServerMessage::AccountMessage(msg) => account_message(msg),
+        ServerMessage::Direct(msg) => direct(msg),
+        ServerMessage::Response(response) => self::response(response),
+        ServerMessage::Event(e) => event(e),
+        ServerMessage::Pong => "pong".to_string(),
+        ServerMessage::Receipt(r) => receipt(r),
+        ServerMessage::Motd(motd) => motd.clone(),
+    }
+}
+
+/// renders the response, lists are rendered one item per line
+pub fn response(response: &Response) -> String {
+    match response {
+        Response::RoomsList(rooms) => lines(rooms.iter().map(room)),
+        Response::RoomAccountsList(accounts) => {
+            lines(accounts.iter().map(|a| a.username().to_string()))
+        }
+        Response::Thread(messages) => thread(messages),
+        Response::Mentions(mentions) => lines(mentions.iter().map(mention)),
+        Response::WhoIs(Some(p)) => presence(p),
+        Response::WhoIs(None) => "unknown user".to_string(),
+        Response::OnlineList(page) => online_page(page),
+        Response::Keys(username, bundle) => keys(username, bundle.as_ref()),
+        Response::Directs(directs) => lines(directs.iter().map(direct)),
+        Response::Receipts(receipts) => lines(receipts.iter().map(receipt)),
+        Response::ScramChallenge(_) => "challenge received".to_string(),
+        Response::ScramSignature(_) => "server signature received".to_string(),
+        Response::SignInResult(Ok(())) => "signed in".to_string(),
+        Response::SignInResult(Err(e)) => format!("failed to sign in: {}", e),
+        Response::UnknownMessage(id) => format!("message #{} does not exist", id),
+        Response::UnknownUser(username) => format!("user '{}' does not exist", username),
+    }
+}
+
+/// renders the page of online accounts, one presence per line
+pub fn online_page(page: &OnlinePage) -> String {
+    let mut buffer = format!("online, page {} of {}:", page.page + 1, page.pages.max(1));
+    for p in &page.accounts {
+        write!(buffer, "\n{}", presence(p)).unwrap();
+    }
+
+    buffer
+}
+
+/// joins the lines
+fn lines(lines: impl Iterator<Item = String>) -> String {
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+/// renders the message in one line with following format:
+///     [TIME] #[MESSAGE ID] [USERNAME]: [TEXT] ([REACTION] [COUNT]..)
+/// if the message mentions the user it starts with '@'
+pub fn account_message(msg: &AccountMessage) -> String {
+    let mut buffer = format!(
+        "{}[{}] #{} {}: {}",
+        if msg.mentioned { "@ " } else { "" },
+        msg.utc.with_timezone(&Local).format("%H:%M:%S"),
+        msg.id,
+        msg.adresser.username(),
+        msg.text,
+    );
+
+    if !msg.reactions.is_empty() {
+        let reactions = msg
+            .reactions
+            .iter()
+            .map(|(r, c)| format!("{} {}", r, c))
+            .collect::<Vec<_>>();
+        write!(buffer, " ({})", reactions.join(", ")).unwrap();
+    }
+
+    buffer
+}
+
+/// renders the direct message in one line with following format:
+///     [TIME] >> [USERNAME]: <encrypted>
+/// the console has no keys, so the text of the message is never shown
+pub fn direct(msg: &DirectMessage) -> String {
+    format!(
+        "[{}] >> {}: <encrypted>",
+        msg.utc.with_timezone(&Local).format("%H:%M:%S"),
+        msg.adresser.username(),
+    )
+}
+
+/// renders the room name with the number of unread messages if there are any
+pub fn room(room: &RoomInfo) -> String {
+    match room.unread {
+        0 => room.name.to_string(),
+        unread => format!("{} ({} unread)", room.name, unread),
+    }
+}
+
+/// renders status of the sent direct message, e.g. '#3 delivered to bob'
+pub fn receipt(receipt: &Receipt) -> String {
+    let status = match receipt.status {
+        DirectStatus::Delivered => "delivered to",
+        DirectStatus::Read => "read by",
+    };
+    format!("#{} {} {}", receipt.id, status, receipt.recipient)
+}
+
+/// renders fingerprint of the published keys of the account
+pub fn keys(username: &Username, bundle: Option<&KeyBundle>) -> String {
+    match bundle {
+        Some(bundle) => format!("{} keys: {}", username, bundle.fingerprint()),
+        None => format!("{} has not published keys", username),
+    }
+}
+
+/// renders the mention in one line with the room name before the message
+pub fn mention(mention: &Mention) -> String {
+    format!("<{}> {}", mention.room, account_message(&mention.message))
+}
+
+/// renders the presence in one line with following format:
+///     [USERNAME] is [STATUS] since [TIME] ([STATUS MESSAGE])
+pub fn presence(presence: &Presence) -> String {
+    let mut buffer = format!(
+        "{} is {:?} since {}",
+        presence.account.username(),
+        presence.status,
+        presence
+            .last_seen
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S"),
+    );
+
+    if !presence.message.is_empty() {
+        write!(buffer, " ({})", presence.message).unwrap();
+    }
+
+    buffer
+}
+
+/// renders the event in one line
+pub fn event(event: &Event) -> String {
+    match event {
+        Event::Reaction(e) => format!(
+            "{} {} {} to #{} ({} {})",
+            e.adresser.username(),
+            if e.added { "added" } else { "removed" },
+            e.reaction,
+            e.id,
+            e.reaction,
+            e.count,
+        ),
+        Event::Typing(account, true) => format!("{} is typing...", account.username()),
+        Event::Typing(account, false) => format!("{} stopped typing", account.username()),
+        Event::Leave(account) => format!("{} left the room", account.username()),
+        Event::Shutdown => "server is shutting down".to_string(),
+        Event::Announcement(text) => format!("announcement: {}", text),
+        Event::RoomDeleted(room) => format!("room '{}' is deleted", room),
+    }
+}
+
+/// renders the thread, each reply is indented under its parent;
+/// expects that each reply follows its parent
+pub fn thread(messages: &[AccountMessage]) -> String {
+    let mut depths = HashMap::<MessageId, usize>::new();
+    let mut buffer = String::new();
+
+    for msg in messages {
+        let depth = msg.parent.and_then(|p| depths.get(&p)).map_or(0, |d| d + 1);
+        depths.insert(msg.id, depth);
+
+        writeln!(
+            buffer,
+            "{:indent$}{}",
+            "",
+            account_message(msg),
+            indent = depth * INDENT
+        )
+        .unwrap();
+    }
+
+    buffer
+}
@@ -0,0 +1,80 @@
+// Admin socket of the running server for operators on the same host:
+//     - access is restricted by permissions of the socket file
+//     - each request is answered by one response, see 'rustenger_shared::admin'
+//     - commands act on 'Server' the same way as the server itself does
+use crate::config::Reloader;
+use futures::{SinkExt, StreamExt};
+use rustenger_shared::{
+    admin::{AdminRequest, AdminResponse, AdminServerCodec},
+    codec::Format,
+};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::codec::Framed;
+
+/// accepts operators and processes each of them in a separate task
+pub async fn listen(mut listener: UnixListener, reloader: Reloader) {
+    let mut incoming = listener.incoming();
+    while let Some(res) = incoming.next().await {
+        if let Ok(stream) = res.inspect_err(|e| log::error!("failed to accept admin stream: {}", e))
+        {
+            log::info!("accept admin stream");
+            tokio::spawn(process(stream, reloader.clone()));
+        }
+    }
+}
+
+/// answers requests of the operator until it disconnects
+async fn process(stream: UnixStream, reloader: Reloader) {
+    let mut framed = Framed::new(stream, AdminServerCodec::with_format(Format::Json));
+    while let Some(res) = framed.next().await {
+        let request = match res {
+            Ok(request) => request,
+            Err(e) => {
+                log::error!("failed to read admin request: {}", e);
+                return;
+            }
+        };
+
+        log::info!("admin request: {:?}", request);
+        let response = handle(request, &reloader).await;
+        if let Err(e) = framed.send(response).await {
+            log::error!("failed to write admin response: {}", e);
+            return;
+        }
+    }
+}
+
+/// executes the request, failures are reported to the operator
+async fn handle(request: AdminRequest, reloader: &Reloader) -> AdminResponse {
+    use AdminRequest::*;
+
+    let server = reloader.server();
+    match request {
+        Rooms => AdminResponse::Rooms(server.room_stats().await),
+        Users => AdminResponse::Users(server.presences().await),
+        Kick(username) => match server.kick(username).await {
+            Ok(()) => AdminResponse::Done,
+            Err(e) => AdminResponse::Error(e.to_string()),
+        },
+        Ban(username) => match server.ban(username).await {
+            Ok(()) => AdminResponse::Done,
+            Err(e) => AdminResponse::Error(format!("user '{}' is banned, but {}", username, e)),
+        },
+        Unban(username) => match server.unban(username).await {
+            true => AdminResponse::Done,
+            false => AdminResponse::Error(format!("user '{}' is not banned", username)),
+        },
+        Announce(text) => {
+            server.announce(text).await;
+            AdminResponse::Done
+        }
+        DeleteRoom(name) => match server.delete_room(name).await {
+            Ok(()) => AdminResponse::Done,
+            Err(e) => AdminResponse::Error(e.to_string()),
+        },
+        Reload => match reloader.reload().await {
+            Ok(changes) => AdminResponse::Reloaded(changes),
+            Err(e) => AdminResponse::Error(e.to_string()),
+        },
+    }
+}
@@ -1,46 +1,165 @@
-use crate::room::{Error, Result, Server};
-use crate::utils::framed_read;
-use futures::SinkExt;
+use crate::room::{Error, LobbyMsg, Result, Server, SessionId};
+use crate::utils::{self, framed_read, ServerFramed, Stream};
+use futures::{
+    future::{self, FutureExt},
+    SinkExt,
+};
 use rustenger_shared::{
-    account::{Account, Color, Password, Username},
-    codec::ServerCodec,
-    message::{ClientMessage, Command, Response, ServerMessage, SignInError},
+    account::{Account, Color, Password, Status, StatusMessage, Username},
+    codec::{Preface, ServerCodec, PREFACE_LEN},
+    e2e::{EncryptedMessage, KeyBundle},
+    message::{
+        ClientMessage, Command, DirectStatus, Event, MessageId, Response, ServerMessage,
+        SignInError,
+    },
+    scram::{Challenge, Credentials, Nonce, Proof},
     RoomName,
 };
 use std::{fmt, result};
-use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
+use tokio::{
+    io::AsyncReadExt,
+    sync::broadcast::{self, RecvError},
+    time::{self, Instant},
+};
+use tokio_util::codec::{Framed, FramedParts};
+
+/// reason of the client outside rooms to wake up
+enum Wake {
+    Read(Result<ClientMessage>),
+    Shutdown,
+    Lobby(LobbyMsg),
+}
+
+/// waits for the next message of the server to the client with 'username' outside rooms
+async fn lobby_msg(username: Username, lobby: &mut broadcast::Receiver<LobbyMsg>) -> LobbyMsg {
+    loop {
+        match lobby.recv().await {
+            Ok(LobbyMsg::Kick(kicked)) if kicked != username => continue,
+            Ok(msg) => return msg,
+            Err(RecvError::Lagged(n)) => log::warn!("user '{}' missed {} messages", username, n),
+            // the sender lives as long as the server, so it is not reached
+            Err(RecvError::Closed) => future::pending().await,
+        }
+    }
+}
 
 pub struct Client {
-    framed: Framed<TcpStream, ServerCodec>,
+    framed: ServerFramed,
     account: Account,
     server: Server,
+    /// id of the session the presence of the user is reported by
+    session: SessionId,
+    /// time of the last frame received from the user
+    last_read: Instant,
 }
 
 impl Client {
-    /// creates new client
-    pub async fn new(stream: TcpStream, server: Server) -> Result<Option<Self>> {
-        let codec = ServerCodec::new();
-        let mut framed = Framed::new(stream, codec);
+    /// creates new client, 'username' is given if the stream is already authenticated,
+    /// e.g. by client certificate, otherwise the user has to sign in
+    pub async fn new(
+        stream: Box<dyn Stream>,
+        username: Option<Username>,
+        server: Server,
+    ) -> Result<Option<Self>> {
+        let framed = Self::negotiate(stream, &server).await?;
+        Self::with_framed(framed, username, server).await
+    }
+
+    /// creates new client over the connection carrying whole messages, e.g. WebSocket
+    pub async fn with_framed(
+        mut framed: ServerFramed,
+        username: Option<Username>,
+        server: Server,
+    ) -> Result<Option<Self>> {
+        let account = match username {
+            Some(username) => Self::authenticated(&mut framed, &server, username).await?,
+            None => Self::sign_in(&mut framed, &server).await?,
+        };
+        let account = match account {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        let session = server.online(account).await;
+        server.connect();
 
-        let client = Self::sign_in(&mut framed).await?.map(|account| Self {
+        // created before anything is sent, so the user is disconnected by Drop on failure
+        let mut client = Self {
             framed,
             account,
             server,
-        });
+            session,
+            last_read: Instant::now(),
+        };
+
+        if let Some(motd) = client.server.motd().await {
+            client.write(ServerMessage::Motd(motd)).await?;
+        }
+
+        Ok(Some(client))
+    }
 
-        Ok(client)
+    /// reads the preface and chooses the format and the compression of the connection,
+    /// connections without preface are uncompressed bincode ones
+    async fn negotiate(mut stream: Box<dyn Stream>, server: &Server) -> Result<ServerFramed> {
+        let mut bytes = [0; PREFACE_LEN];
+        let idle_timeout = server.idle_timeout().await;
+        time::timeout(idle_timeout, stream.read_exact(&mut bytes))
+            .await
+            .map_err(|_| Error::IdleTimeout(idle_timeout))??;
+
+        let (preface, first_bytes) = match Preface::parse(&bytes)? {
+            Some(preface) => (preface, &[][..]),
+            None => (Preface::DEFAULT, &bytes[..]),
+        };
+        log::debug!(
+            "connection format: {:?}, compression: {:?}",
+            preface.format,
+            preface.compression
+        );
+
+        let max_frame_size = server.max_frame_size().await;
+        let codec = ServerCodec::with_preface(preface).max_frame_size(max_frame_size);
+        let mut parts = FramedParts::new(stream, codec);
+        parts.read_buf.extend_from_slice(first_bytes);
+        Ok(Box::new(Framed::from_parts(parts)))
     }
 
     /// log in or sign up user, user can exit at that moment and then Ok(None) is returned
-    async fn sign_in(framed: &mut Framed<TcpStream, ServerCodec>) -> Result<Option<Account>> {
+    async fn sign_in(framed: &mut ServerFramed, server: &Server) -> Result<Option<Account>> {
+        // challenge-response log in waiting for the proof
+        let mut scram = None;
+        let mut last_read = Instant::now();
+
         loop {
-            if let ClientMessage::Command(cmd) = framed_read(framed).await? {
+            let idle_timeout = server.idle_timeout().await;
+            let msg = match framed_read(framed, &mut last_read, idle_timeout).await {
+                Err(Error::Disconnected) => {
+                    log::info!("user disconnected before sign in");
+                    return Ok(None);
+                }
+                res => res?,
+            };
+            if let ClientMessage::Command(cmd) = msg {
                 use Command::*;
 
                 let res = match cmd {
-                    LogIn(un, pw) => Self::log_in(un, pw),
-                    SignUp(un, pw) => Self::sing_up(un, pw),
+                    LogIn(un, pw) => Self::log_in(server, un, pw).await,
+                    SignUp(un, pw) => Self::sing_up(server, un, pw).await,
+                    ScramStart(un, nonce) => {
+                        let state = ScramState::new(server, un, nonce).await;
+                        let response = Response::ScramChallenge(state.challenge);
+                        framed.send(ServerMessage::Response(response)).await?;
+
+                        scram = Some(state);
+                        continue;
+                    }
+                    ScramProof(proof) => match scram.take() {
+                        Some(state) => Self::scram_proof(framed, server, state, proof).await?,
+                        None => {
+                            log::warn!("unexpected proof without challenge");
+                            continue;
+                        }
+                    },
                     Exit => return Ok(None),
                     cmd => {
                         log::warn!("untreated command: {:?}", cmd);
@@ -48,6 +167,10 @@ impl Client {
                     }
                 };
 
+                let res = match res {
+                    Ok(acc) if server.is_banned(acc.username()).await => Err(SignInError::Banned),
+                    res => res,
+                };
                 let response = Response::SignInResult(res.clone().map(|_| ()));
                 framed.send(ServerMessage::Response(response)).await?;
 
@@ -62,32 +185,108 @@ impl Client {
         }
     }
 
-    // TODO
+    /// skips sign in of already authenticated user and notifies the user about it,
+    /// banned user is notified as well and then Ok(None) is returned
+    async fn authenticated(
+        framed: &mut ServerFramed,
+        server: &Server,
+        username: Username,
+    ) -> Result<Option<Account>> {
+        log::info!("user is already authenticated: {}", username);
+
+        if server.is_banned(username).await {
+            log::warn!("banned user '{}' is refused", username);
+            let response = Response::SignInResult(Err(SignInError::Banned));
+            framed.send(ServerMessage::Response(response)).await?;
+            return Ok(None);
+        }
+
+        let response = Response::SignInResult(Ok(()));
+        framed.send(ServerMessage::Response(response)).await?;
+
+        Ok(Some(Account::new(username)))
+    }
+
     /// finds an account by name and returns it if the passwords match
-    fn log_in(username: Username, _password: Password) -> result::Result<Account, SignInError> {
+    async fn log_in(
+        server: &Server,
+        username: Username,
+        password: Password,
+    ) -> result::Result<Account, SignInError> {
         log::info!("attempt to log in: {}", username);
 
-        let err = SignInError::InvalidUserNamePassword;
-        Err(err)
+        let credentials = match server.credentials(username).await {
+            Some(credentials) => credentials,
+            None => return Err(SignInError::InvalidUserNamePassword),
+        };
+
+        if utils::blocking(move || credentials.verify_password(&password)).await {
+            Ok(Account::new(username))
+        } else {
+            Err(SignInError::InvalidUserNamePassword)
+        }
     }
 
-    // TODO
     /// if an account with the same name does not exist, creates it
-    fn sing_up(username: Username, _password: Password) -> result::Result<Account, SignInError> {
+    async fn sing_up(
+        server: &Server,
+        username: Username,
+        password: Password,
+    ) -> result::Result<Account, SignInError> {
         log::info!("attempt to sing up: {}", username);
 
-        let acc = Account::new(username);
-        Ok(acc)
+        server.register(username, password).await?;
+        Ok(Account::new(username))
     }
 
-    /// reads a message from the user
+    /// checks the proof of challenge-response log in and sends the server signature if it is valid,
+    /// banned user gets no signature
+    async fn scram_proof(
+        framed: &mut ServerFramed,
+        server: &Server,
+        state: ScramState,
+        proof: Proof,
+    ) -> Result<result::Result<Account, SignInError>> {
+        log::info!(
+            "attempt to log in by challenge-response: {}",
+            state.username
+        );
+
+        let signature = state.credentials.and_then(|c| {
+            c.verify_proof(
+                &state.username,
+                &state.client_nonce,
+                &state.challenge,
+                &proof,
+            )
+        });
+
+        match signature {
+            Some(_) if server.is_banned(state.username).await => Ok(Err(SignInError::Banned)),
+            Some(signature) => {
+                let response = Response::ScramSignature(signature);
+                framed.send(ServerMessage::Response(response)).await?;
+                Ok(Ok(Account::new(state.username)))
+            }
+            None => Ok(Err(SignInError::InvalidUserNamePassword)),
+        }
+    }
+
+    /// reads a message from the user, fails if the user is silent longer than idle timeout
     pub async fn read(&mut self) -> Result<ClientMessage> {
-        framed_read(&mut self.framed).await
+        let idle_timeout = self.server.idle_timeout().await;
+        framed_read(&mut self.framed, &mut self.last_read, idle_timeout).await
+    }
+
+    /// notifies the user that the server is shutting down and closes the connection
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.write(ServerMessage::Event(Event::Shutdown)).await?;
+        self.framed.close().await.map_err(Error::Codec)
     }
 
     /// sends a message to the user
     pub async fn write(&mut self, msg: ServerMessage) -> Result<()> {
-        self.framed.send(msg).await.map_err(Error::Bincode)
+        self.framed.send(msg).await.map_err(Error::Codec)
     }
 
     /// returns account
@@ -109,14 +308,58 @@ impl Client {
     pub async fn run(mut self) -> Result<()> {
         log::info!("run client: {}", self.username());
 
+        let server = self.server.clone();
+        let username = self.username();
+        let mut lobby = server.lobby();
         loop {
-            if let ClientMessage::Command(cmd) = self.read().await? {
-                match self.handle(cmd).await? {
+            // the read is dropped on shutdown or message of the server, so the client can be notified
+            let (wake, _, _) = future::select_all(vec![
+                self.read().map(Wake::Read).boxed(),
+                server.shutting_down().map(|_| Wake::Shutdown).boxed(),
+                lobby_msg(username, &mut lobby).map(Wake::Lobby).boxed(),
+            ])
+            .await;
+            let msg = match wake {
+                Wake::Shutdown => return self.shutdown().await,
+                Wake::Lobby(LobbyMsg::Kick(_)) => {
+                    log::info!("user '{}' is kicked", username);
+                    return Ok(());
+                }
+                Wake::Lobby(LobbyMsg::Announce(text)) => {
+                    self.write(ServerMessage::Event(Event::Announcement(text)))
+                        .await?;
+                    continue;
+                }
+                Wake::Read(Err(Error::Disconnected)) => {
+                    log::info!("user '{}' disconnected", username);
+                    return Ok(());
+                }
+                Wake::Read(res) => res?,
+            };
+
+            // banned in the lobby, users in rooms are disconnected by the room
+            if self.server.is_banned(self.username()).await {
+                log::info!("disconnect banned user '{}'", self.username());
+                return Ok(());
+            }
+
+            match msg {
+                ClientMessage::Command(cmd) => match self.handle(cmd).await? {
                     None => return Ok(()),
                     Some(client) => {
                         self = client;
                     }
+                },
+                ClientMessage::Direct(recipient, payload) => {
+                    self.direct(recipient, payload).await?;
                 }
+                ClientMessage::Ack(id) => {
+                    let status = DirectStatus::Delivered;
+                    if let Err(e) = self.server.receipt(self.username(), id, status).await {
+                        log::warn!("failed to acknowledge direct message: {}", e);
+                    }
+                }
+                _ => (),
             }
         }
     }
@@ -130,7 +373,17 @@ impl Client {
             SelectRoom(rn) => self.select_room(rn).await,
             ExitRoom => self.exit_room().await,
             RoomsList => self.room_list().await,
-            SelectColor(c) => self.select_color(c),
+            SelectColor(c) => self.select_color(c).await,
+            Mentions => self.mentions().await,
+            SetStatus(s, m) => self.set_status(s, m).await,
+            WhoIs(un) => self.who_is(un).await,
+            WhoIsOnline(page) => self.who_is_online(page).await,
+            PublishKeys(bundle) => self.publish_keys(bundle).await,
+            FetchKeys(un) => self.fetch_keys(un).await,
+            Directs => self.directs().await,
+            MarkRead(rn, id) => self.mark_read(rn, id).await,
+            MarkDirectRead(id) => self.mark_direct_read(id).await,
+            Receipts => self.receipts().await,
             // DeleteAccount => (), TODO
             Exit => self.exit(),
             cmd => {
@@ -157,23 +410,125 @@ impl Client {
     }
 
     // async fn exit_room(self) -> Result<Option<Self>> {
-    fn exit_room(self) -> impl std::future::Future<Output = Result<Option<Self>>> + Send {
+    // the future type would be cyclic: it spawns 'run' which awaits 'handle' which awaits it
+    #[allow(clippy::manual_async_fn)]
+    pub fn exit_room(self) -> impl std::future::Future<Output = Result<Option<Self>>> + Send {
         async move {
+            self.server.set_room(self.username(), None).await;
             tokio::spawn(self.run());
             Ok(None)
         }
     }
 
     async fn room_list(mut self) -> Result<Option<Self>> {
-        let rooms = self.server.rooms().await;
+        let rooms = self.server.rooms(self.username()).await;
         let response = Response::RoomsList(rooms);
         let serv_message = ServerMessage::Response(response);
 
         self.write(serv_message).await.map(|_| Some(self))
     }
 
-    fn select_color(mut self, color: Color) -> Result<Option<Self>> {
+    async fn mentions(mut self) -> Result<Option<Self>> {
+        let mentions = self.server.take_mentions(self.username()).await;
+        let response = Response::Mentions(mentions);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    async fn set_status(self, status: Status, message: StatusMessage) -> Result<Option<Self>> {
+        if status == Status::Offline {
+            log::warn!("user '{}' attempted to set offline status", self.username());
+        } else {
+            self.server
+                .set_status(self.username(), status, message)
+                .await;
+        }
+
+        Ok(Some(self))
+    }
+
+    async fn who_is(mut self, username: Username) -> Result<Option<Self>> {
+        let presence = self.server.presence(username).await;
+        let response = Response::WhoIs(presence);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    async fn who_is_online(mut self, page: u32) -> Result<Option<Self>> {
+        let page = self.server.online_page(page).await;
+        let response = Response::OnlineList(page);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    async fn publish_keys(self, bundle: KeyBundle) -> Result<Option<Self>> {
+        self.server.publish_keys(self.username(), bundle).await;
+        Ok(Some(self))
+    }
+
+    async fn fetch_keys(mut self, username: Username) -> Result<Option<Self>> {
+        let keys = self.server.keys(username).await;
+        let response = Response::Keys(username, keys);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    async fn directs(mut self) -> Result<Option<Self>> {
+        let directs = self.server.take_directs(self.username()).await;
+        let response = Response::Directs(directs);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    async fn mark_read(self, room_name: RoomName, id: MessageId) -> Result<Option<Self>> {
+        if let Err(e) = self.server.mark_read(self.username(), room_name, id).await {
+            log::warn!("failed to mark messages of '{}' as read: {}", room_name, e);
+        }
+        Ok(Some(self))
+    }
+
+    async fn mark_direct_read(self, id: MessageId) -> Result<Option<Self>> {
+        let status = DirectStatus::Read;
+        if let Err(e) = self.server.receipt(self.username(), id, status).await {
+            log::warn!("failed to mark direct message as read: {}", e);
+        }
+        Ok(Some(self))
+    }
+
+    async fn receipts(mut self) -> Result<Option<Self>> {
+        let receipts = self.server.take_receipts(self.username()).await;
+        let response = Response::Receipts(receipts);
+        let serv_message = ServerMessage::Response(response);
+
+        self.write(serv_message).await.map(|_| Some(self))
+    }
+
+    /// sends direct message while the client is not in a room
+    async fn direct(&mut self, recipient: Username, payload: EncryptedMessage) -> Result<()> {
+        let res = self
+            .server
+            .direct_message(self.account, recipient, payload)
+            .await;
+        match res {
+            Ok(msg) => self.server.deliver(msg).await,
+            Err(Error::UserDoesNotExist(un)) => {
+                log::warn!("user '{}' refers to unknown user '{}'", self.username(), un);
+                let response = Response::UnknownUser(un);
+                self.write(ServerMessage::Response(response)).await?;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    async fn select_color(mut self, color: Color) -> Result<Option<Self>> {
         self.set_color(color);
+        self.server.update_account(self.account).await;
         Ok(Some(self))
     }
 
@@ -183,8 +538,79 @@ impl Client {
     }
 }
 
+/// challenge-response log in waiting for the proof
+struct ScramState {
+    username: Username,
+    client_nonce: Nonce,
+    challenge: Challenge,
+    /// 'None' if the account does not exist
+    credentials: Option<Credentials>,
+}
+
+impl ScramState {
+    /// creates challenge for the account, for unknown accounts the challenge is made up,
+    /// so that the response does not reveal whether the account exists
+    async fn new(server: &Server, username: Username, client_nonce: Nonce) -> Self {
+        let credentials = server.credentials(username).await;
+        let challenge = match credentials {
+            Some(credentials) => credentials.challenge(),
+            None => server.unknown_challenge(username),
+        };
+
+        Self {
+            username,
+            client_nonce,
+            challenge,
+            credentials,
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        log::debug!("drop the client: {}", self.username());
+        self.server.disconnect();
+
+        let fut = self.server.clone().offline(self.username(), self.session);
+        utils::spawn_cleanup(fut);
+    }
+}
+
 impl fmt::Debug for Client {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Client {{ framed: .., account: {:?} }}", self.account)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{self, open, recv, send};
+    use rustenger_shared::scram::ClientExchange;
+
+    #[tokio::test]
+    async fn banned_user_gets_no_scram_signature() {
+        let server = Server::new(&Config::default());
+        let bob = testing::username("bob");
+        let password = Password::from("secret").unwrap();
+        server.register(bob, password).await.unwrap();
+        server.ban(bob).await.unwrap();
+
+        let mut client = open(&server, None).await;
+        let exchange = ClientExchange::new(bob, password);
+        let start = Command::ScramStart(bob, exchange.nonce());
+        send(&mut client, ClientMessage::Command(start)).await;
+        let challenge = match recv(&mut client).await {
+            ServerMessage::Response(Response::ScramChallenge(challenge)) => challenge,
+            msg => panic!("unexpected message: {:?}", msg),
+        };
+
+        let (proof, _) = exchange.proof(&challenge).unwrap();
+        send(&mut client, ClientMessage::Command(Command::ScramProof(proof))).await;
+        match recv(&mut client).await {
+            ServerMessage::Response(Response::SignInResult(Err(SignInError::Banned))) => (),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+// Configuration of the server, each setting is taken from the first source which has it:
+//     - command line arguments
+//     - environment variables, 'RUSTENGER_*', e.g. 'RUSTENGER_ADDRESSES=[::1]:4732,127.0.0.1:4732'
+//     - config file given by '--config'
+//     - defaults
+use crate::{room::Server, utils};
+use clap::ArgMatches;
+use log::LevelFilter;
+use rustenger_shared::{account::Username, codec::DEFAULT_MAX_FRAME_SIZE};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env,
+    fmt::{Debug, Display},
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+const DEFAULT_ADDR: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
+const DEFAULT_PORT: u16 = 4732;
+/// the owner and the group of the server can connect to the unix socket
+const DEFAULT_UNIX_MODE: u32 = 0o660;
+/// only the owner of the server can connect to the admin socket
+const DEFAULT_ADMIN_MODE: u32 = 0o600;
+const PATH_TO_MESENGES_LOG: &str = "messenges.log";
+const PATH_TO_GENERAL_LOG: &str = "general.log";
+/// clients are expected to ping more often, the console client pings every 30 seconds
+const DEFAULT_IDLE_TIMEOUT: u64 = 90;
+const DEFAULT_ROOM_CHANNEL_CAPACITY: usize = 64;
+const DEFAULT_INBOX_CAPACITY: usize = 256;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+/// the top bit of the frame head is the compression flag
+const MAX_FRAME_SIZE_LIMIT: usize = 0x7fff_ffff;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("failed to read config file '{path}': {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid config file '{path}': {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    #[error("invalid value '{value}' of '--{name}': {reason}")]
+    Arg {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// message of the day sent to users after sign in
+    pub motd: Option<String>,
+    /// accounts which are not allowed to sign in
+    pub bans: Vec<Username>,
+    pub listen: Listen,
+    pub tls: Tls,
+    pub log: Log,
+    pub limits: Limits,
+}
+
+/// addresses the server accepts clients on
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listen {
+    /// addresses of framed listeners
+    pub addresses: Vec<SocketAddr>,
+    /// addresses of WebSocket listeners
+    pub ws: Vec<SocketAddr>,
+    /// path of unix socket
+    pub unix: Option<PathBuf>,
+    /// permissions of unix socket
+    pub unix_mode: u32,
+    /// path of admin socket
+    pub admin: Option<PathBuf>,
+    /// permissions of admin socket
+    pub admin_mode: u32,
+}
+
+impl Default for Listen {
+    fn default() -> Self {
+        Self {
+            addresses: vec![(DEFAULT_ADDR, DEFAULT_PORT).into()],
+            ws: Vec::new(),
+            unix: None,
+            unix_mode: DEFAULT_UNIX_MODE,
+            admin: None,
+            admin_mode: DEFAULT_ADMIN_MODE,
+        }
+    }
+}
+
+/// PEM files of TLS, it is enabled only if the certificate is given
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tls {
+    /// certificate chain of the server
+    pub cert: Option<PathBuf>,
+    /// private key of the server
+    pub key: Option<PathBuf>,
+    /// CA certificates of clients, enables authentication by client certificates
+    pub client_ca: Option<PathBuf>,
+    /// revoked client certificates
+    pub crl: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    /// file of user messages
+    pub messages: PathBuf,
+    /// file of other logs
+    pub general: PathBuf,
+    /// level of logs written to 'general'
+    pub level: LevelFilter,
+    /// level of logs written to stdout
+    pub stdout_level: LevelFilter,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            messages: PATH_TO_MESENGES_LOG.into(),
+            general: PATH_TO_GENERAL_LOG.into(),
+            level: LevelFilter::Info,
+            stdout_level: LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// maximum size of frames sent and received by clients
+    pub max_frame_size: usize,
+    /// seconds after which clients sending nothing are disconnected
+    pub idle_timeout: u64,
+    /// number of messages queued for a room
+    pub room_channel_capacity: usize,
+    /// maximum number of mentions kept for an account, the oldest are dropped
+    pub mention_inbox_capacity: usize,
+    /// maximum number of undelivered direct messages kept for an account, the oldest are dropped
+    pub direct_inbox_capacity: usize,
+    /// maximum number of undelivered receipts kept for an account, the oldest are dropped
+    pub receipt_inbox_capacity: usize,
+    /// seconds the server waits for clients to disconnect on shutdown
+    pub shutdown_timeout: u64,
+}
+
+impl Limits {
+    /// clients which send nothing during this time are disconnected
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout)
+    }
+
+    /// the server waits this time for clients to disconnect on shutdown
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    /// checks that limits are usable, e.g. zero capacity of channel is not
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: String| Err(Error::Invalid(msg));
+
+        if self.max_frame_size == 0 || self.max_frame_size > MAX_FRAME_SIZE_LIMIT {
+            let msg = format!(
+                "'limits.max_frame_size' must be in 1..={}",
+                MAX_FRAME_SIZE_LIMIT
+            );
+            return invalid(msg);
+        }
+        if self.idle_timeout == 0 {
+            return invalid("'limits.idle_timeout' must be positive".into());
+        }
+        for (name, capacity) in [
+            ("room_channel_capacity", self.room_channel_capacity),
+            ("mention_inbox_capacity", self.mention_inbox_capacity),
+            ("direct_inbox_capacity", self.direct_inbox_capacity),
+            ("receipt_inbox_capacity", self.receipt_inbox_capacity),
+        ] {
+            if capacity == 0 {
+                return invalid(format!("'limits.{}' must be positive", name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            room_channel_capacity: DEFAULT_ROOM_CHANNEL_CAPACITY,
+            mention_inbox_capacity: DEFAULT_INBOX_CAPACITY,
+            direct_inbox_capacity: DEFAULT_INBOX_CAPACITY,
+            receipt_inbox_capacity: DEFAULT_INBOX_CAPACITY,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+impl Config {
+    /// reads the config file if '--config' is given, applies the arguments over it and validates
+    pub fn load(matches: &ArgMatches) -> Result<Self> {
+        let mut config = match matches.value_of("config") {
+            Some(path) => Self::read(Path::new(path))?,
+            None => Self::default(),
+        };
+
+        config.apply_args(matches)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// reads the config file, missing settings are set to defaults
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|source| Error::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        toml::from_str(&content).map_err(|source| Error::Parse {
+            path: path.display().to_string(),
+            source,
+        })
+    }
+
+    /// replaces settings by the given arguments
+    fn apply_args(&mut self, matches: &ArgMatches) -> Result<()> {
+        let listen = &mut self.listen;
+        if let Some(addrs) = values(matches, "addresses", "RUSTENGER_ADDRESSES")? {
+            listen.addresses = addrs;
+        }
+        if let Some(addrs) = values(matches, "ws", "RUSTENGER_WS")? {
+            listen.ws = addrs;
+        }
+        if let Some(path) = matches.value_of("unix") {
+            listen.unix = Some(path.into());
+        }
+        if let Some(mode) = matches.value_of("unix-mode") {
+            listen.unix_mode =
+                u32::from_str_radix(mode, 8).map_err(|e| arg_error("unix-mode", mode, e))?;
+        }
+        if let Some(path) = matches.value_of("admin") {
+            listen.admin = Some(path.into());
+        }
+        if let Some(mode) = matches.value_of("admin-mode") {
+            listen.admin_mode =
+                u32::from_str_radix(mode, 8).map_err(|e| arg_error("admin-mode", mode, e))?;
+        }
+
+        let tls = &mut self.tls;
+        for (name, path) in [
+            ("cert", &mut tls.cert),
+            ("key", &mut tls.key),
+            ("client-ca", &mut tls.client_ca),
+            ("crl", &mut tls.crl),
+        ] {
+            if let Some(value) = matches.value_of(name) {
+                *path = Some(value.into());
+            }
+        }
+
+        let log = &mut self.log;
+        if let Some(level) = value(matches, "log-level")? {
+            log.level = level;
+        }
+
+        let limits = &mut self.limits;
+        if let Some(size) = value(matches, "max-frame-size")? {
+            limits.max_frame_size = size;
+        }
+        if let Some(secs) = value(matches, "idle-timeout")? {
+            limits.idle_timeout = secs;
+        }
+
+        Ok(())
+    }
+
+    /// describes settings which differ in 'new' and are applied on reload,
+    /// e.g. "limits.idle_timeout: 90 -> 30", settings which only affect
+    /// connections or rooms created after the reload are marked so
+    pub fn diff(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        let mut change = |name: &str, old: &dyn Debug, new: &dyn Debug| {
+            let (old, new) = (format!("{:?}", old), format!("{:?}", new));
+            if old != new {
+                let note = match name {
+                    "limits.max_frame_size" => " (applies to new connections)",
+                    "limits.room_channel_capacity" => " (applies to new rooms)",
+                    _ => "",
+                };
+                changes.push(format!("{}: {} -> {}{}", name, old, new, note));
+            }
+        };
+
+        change("motd", &self.motd, &new.motd);
+
+        let (old_log, new_log) = (&self.log, &new.log);
+        change("log.level", &old_log.level, &new_log.level);
+        change(
+            "log.stdout_level",
+            &old_log.stdout_level,
+            &new_log.stdout_level,
+        );
+
+        let (old_limits, new_limits) = (&self.limits, &new.limits);
+        change(
+            "limits.max_frame_size",
+            &old_limits.max_frame_size,
+            &new_limits.max_frame_size,
+        );
+        change(
+            "limits.idle_timeout",
+            &old_limits.idle_timeout,
+            &new_limits.idle_timeout,
+        );
+        change(
+            "limits.room_channel_capacity",
+            &old_limits.room_channel_capacity,
+            &new_limits.room_channel_capacity,
+        );
+        change(
+            "limits.mention_inbox_capacity",
+            &old_limits.mention_inbox_capacity,
+            &new_limits.mention_inbox_capacity,
+        );
+        change(
+            "limits.direct_inbox_capacity",
+            &old_limits.direct_inbox_capacity,
+            &new_limits.direct_inbox_capacity,
+        );
+        change(
+            "limits.receipt_inbox_capacity",
+            &old_limits.receipt_inbox_capacity,
+            &new_limits.receipt_inbox_capacity,
+        );
+        change(
+            "limits.shutdown_timeout",
+            &old_limits.shutdown_timeout,
+            &new_limits.shutdown_timeout,
+        );
+
+        let (old_bans, new_bans): (HashSet<_>, HashSet<_>) =
+            (self.bans.iter().collect(), new.bans.iter().collect());
+        for username in new_bans.difference(&old_bans) {
+            changes.push(format!("bans: +{}", username));
+        }
+        for username in old_bans.difference(&new_bans) {
+            changes.push(format!("bans: -{}", username));
+        }
+
+        changes
+    }
+
+    /// returns names of settings which differ in 'new' but are applied only on restart
+    pub fn restart_required(&self, new: &Self) -> Vec<&'static str> {
+        let mut sections = Vec::new();
+        if self.listen != new.listen {
+            sections.push("listen");
+        }
+        if self.tls != new.tls {
+            sections.push("tls");
+        }
+        if (&self.log.messages, &self.log.general) != (&new.log.messages, &new.log.general) {
+            sections.push("log");
+        }
+        sections
+    }
+
+    /// checks that settings are consistent and within limits
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |msg: &str| Err(Error::Invalid(msg.into()));
+
+        if self.listen.addresses.is_empty() {
+            return invalid("'listen.addresses' is empty");
+        }
+        if self.listen.unix_mode > 0o777 {
+            return invalid("'listen.unix_mode' is not a file mode, expected at most 0o777");
+        }
+        if self.listen.admin_mode > 0o777 {
+            return invalid("'listen.admin_mode' is not a file mode, expected at most 0o777");
+        }
+        if self.listen.admin.is_some() && self.listen.admin == self.listen.unix {
+            return invalid("'listen.admin' and 'listen.unix' are the same path");
+        }
+
+        let tls = &self.tls;
+        if tls.cert.is_some() != tls.key.is_some() {
+            return invalid("'tls.cert' and 'tls.key' are given only together");
+        }
+        if tls.client_ca.is_some() && tls.cert.is_none() {
+            return invalid("'tls.client_ca' requires 'tls.cert'");
+        }
+        if tls.crl.is_some() && tls.client_ca.is_none() {
+            return invalid("'tls.crl' requires 'tls.client_ca'");
+        }
+
+        self.limits.validate()
+    }
+}
+
+/// reloads the config and applies it to the running server,
+/// shared by SIGHUP and the admin socket
+#[derive(Clone)]
+pub struct Reloader {
+    matches: Arc<ArgMatches<'static>>,
+    config: Arc<Mutex<Config>>,
+    server: Server,
+}
+
+impl Reloader {
+    /// 'config' is the one the server is started with
+    pub fn new(matches: ArgMatches<'static>, config: Config, server: Server) -> Self {
+        Self {
+            matches: Arc::new(matches),
+            config: Arc::new(Mutex::new(config)),
+            server,
+        }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// reads the config again, the arguments and the environment still take precedence over the file,
+    /// returns the applied changes, the old config is kept if the new one is invalid
+    pub async fn reload(&self) -> Result<Vec<String>> {
+        let new = Config::load(&self.matches)?;
+        let mut config = self.config.lock().await;
+
+        let changes = config.diff(&new);
+        if changes.is_empty() {
+            log::info!("config has not changed");
+        }
+        for change in &changes {
+            log::info!("config change: {}", change);
+        }
+        for section in config.restart_required(&new) {
+            log::warn!(
+                "config section '{}' has changed, restart to apply it",
+                section
+            );
+        }
+
+        utils::set_log_levels(new.log.level, new.log.stdout_level);
+        self.server.reload(&new).await;
+        *config = new;
+        Ok(changes)
+    }
+}
+
+fn arg_error(name: &'static str, value: &str, reason: impl Display) -> Error {
+    Error::Arg {
+        name,
+        value: value.into(),
+        reason: reason.to_string(),
+    }
+}
+
+/// parses the value of the argument 'name' if it is given
+fn value<T>(matches: &ArgMatches, name: &'static str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    matches
+        .value_of(name)
+        .map(|v| v.parse().map_err(|e| arg_error(name, v, e)))
+        .transpose()
+}
+
+/// parses all values of the argument 'name' if it is given,
+/// otherwise comma separated values of the environment variable 'var',
+/// clap would mix them with the values of the argument
+fn values<T>(matches: &ArgMatches, name: &'static str, var: &str) -> Result<Option<Vec<T>>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let parse = |v: &str| v.trim().parse().map_err(|e| arg_error(name, v, e));
+    match (matches.values_of(name), env::var(var)) {
+        (Some(vs), _) => vs.map(parse).collect::<Result<_>>().map(Some),
+        (None, Ok(vs)) => vs.split(',').map(parse).collect::<Result<_>>().map(Some),
+        (None, Err(_)) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// held by tests loading the config, the environment is shared by them
+    static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// loads the config from the arguments, the first one is the name of the binary
+    fn load(args: &[&str]) -> Result<Config> {
+        let matches = crate::app().get_matches_from_safe(args).unwrap();
+        Config::load(&matches)
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn invalid(config: &Config) -> bool {
+        matches!(config.validate(), Err(Error::Invalid(_)))
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = Config::default();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.listen.addresses, addrs(&["0.0.0.0:4732"]));
+        assert_eq!(config.limits.max_frame_size, DEFAULT_MAX_FRAME_SIZE);
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            bans = ["mallory"]
+            [listen]
+            unix_mode = 0o600
+            [limits]
+            idle_timeout = 30
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.bans, vec![Username::from("mallory").unwrap()]);
+        assert_eq!(config.listen.unix_mode, 0o600);
+        assert_eq!(config.limits.idle_timeout, 30);
+        assert_eq!(config.listen.addresses, Listen::default().addresses);
+        assert_eq!(config.limits.shutdown_timeout, DEFAULT_SHUTDOWN_TIMEOUT);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("[limits]\nidle_timout = 30").is_err());
+        assert!(toml::from_str::<Config>("adresses = []").is_err());
+    }
+
+    #[test]
+    fn arguments_take_precedence_over_environment_and_file() {
+        let _env = ENV.lock().unwrap();
+        let path = env::temp_dir().join(format!("rustenger-test-{}.toml", std::process::id()));
+        let file = r#"
+            [listen]
+            addresses = ["127.0.0.1:1"]
+            [limits]
+            max_frame_size = 1000
+            idle_timeout = 30
+            room_channel_capacity = 7
+            "#;
+        fs::write(&path, file).unwrap();
+        let path = path.to_str().unwrap();
+
+        env::set_var("RUSTENGER_ADDRESSES", "127.0.0.1:2, [::1]:3");
+        env::set_var("RUSTENGER_MAX_FRAME_SIZE", "1500");
+        env::set_var("RUSTENGER_IDLE_TIMEOUT", "40");
+
+        let config = load(&["server", "-c", path, "--max-frame-size", "2000"]).unwrap();
+        assert_eq!(config.limits.max_frame_size, 2000);
+        assert_eq!(config.limits.idle_timeout, 40);
+        assert_eq!(config.limits.room_channel_capacity, 7);
+        // values of the environment are not mixed with the arguments
+        assert_eq!(config.listen.addresses, addrs(&["127.0.0.1:2", "[::1]:3"]));
+
+        let config = load(&["server", "-c", path, "-a", "127.0.0.1:4"]).unwrap();
+        assert_eq!(config.listen.addresses, addrs(&["127.0.0.1:4"]));
+        assert_eq!(config.limits.max_frame_size, 1500);
+
+        env::set_var("RUSTENGER_ADDRESSES", "127.0.0.1:2,nowhere");
+        let res = load(&["server", "-c", path]);
+        assert!(matches!(
+            res,
+            Err(Error::Arg {
+                name: "addresses",
+                ..
+            })
+        ));
+
+        env::remove_var("RUSTENGER_ADDRESSES");
+        env::remove_var("RUSTENGER_MAX_FRAME_SIZE");
+        env::remove_var("RUSTENGER_IDLE_TIMEOUT");
+        let config = load(&["server", "-c", path]).unwrap();
+        assert_eq!(config.listen.addresses, addrs(&["127.0.0.1:1"]));
+        assert_eq!(config.limits.max_frame_size, 1000);
+        assert_eq!(config.limits.idle_timeout, 30);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported() {
+        let _env = ENV.lock().unwrap();
+        let res = load(&["server", "--unix-mode", "999"]);
+        assert!(matches!(
+            res,
+            Err(Error::Arg {
+                name: "unix-mode",
+                ..
+            })
+        ));
+        let res = load(&["server", "--log-level", "loud"]);
+        assert!(matches!(
+            res,
+            Err(Error::Arg {
+                name: "log-level",
+                ..
+            })
+        ));
+        let res = load(&["server", "-c", "/nonexistent/rustenger.toml"]);
+        assert!(matches!(res, Err(Error::Read { .. })));
+    }
+
+    #[test]
+    fn frame_size_is_bounded() {
+        let mut config = Config::default();
+        config.limits.max_frame_size = 0;
+        assert!(invalid(&config));
+        config.limits.max_frame_size = MAX_FRAME_SIZE_LIMIT + 1;
+        assert!(invalid(&config));
+        config.limits.max_frame_size = MAX_FRAME_SIZE_LIMIT;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn zero_limits_are_invalid() {
+        let zeroed: [fn(&mut Limits); 5] = [
+            |l| l.idle_timeout = 0,
+            |l| l.room_channel_capacity = 0,
+            |l| l.mention_inbox_capacity = 0,
+            |l| l.direct_inbox_capacity = 0,
+            |l| l.receipt_inbox_capacity = 0,
+        ];
+        for zero in &zeroed {
+            let mut config = Config::default();
+            zero(&mut config.limits);
+            assert!(invalid(&config), "{:?}", config.limits);
+        }
+    }
+
+    #[test]
+    fn listeners_and_tls_are_consistent() {
+        let mut config = Config::default();
+        config.listen.addresses.clear();
+        assert!(invalid(&config));
+
+        let mut config = Config::default();
+        config.listen.unix_mode = 0o1000;
+        assert!(invalid(&config));
+
+        let mut config = Config::default();
+        config.tls.cert = Some("server.pem".into());
+        assert!(invalid(&config));
+        config.tls.key = Some("server.key".into());
+        assert!(config.validate().is_ok());
+        config.tls.crl = Some("revoked.crl".into());
+        assert!(invalid(&config));
+    }
+}
@@ -1,84 +1,397 @@
-#![feature(async_closure)]
-use crate::utils::ResultExt;
 use futures::{
-    future,
-    stream::{self, StreamExt},
+    future::{self, BoxFuture, Either, FutureExt},
+    stream::StreamExt,
 };
-use std::net::{Ipv4Addr, SocketAddr};
-use tokio::net::{TcpListener, TcpStream};
+use rustenger_shared::account::Username;
+use socket2::{Domain, Socket, Type};
+use std::{error::Error, fs, io, net::SocketAddr, path::Path, process};
+use tokio::{
+    net::{TcpListener, TcpStream, UnixListener},
+    signal::unix::{signal, SignalKind},
+    time,
+};
+
+mod admin;
 
 mod client;
 use client::Client;
 
+mod config;
+use config::{Config, Reloader};
+
 mod room;
 use room::Server;
 
+mod store;
+
+#[cfg(test)]
+mod testing;
+
+mod tls;
+use tls::Acceptor;
+
+mod unix;
+
 mod utils;
+use utils::Stream;
 
-const DEFAULT_ADDR: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
-const DEFAULT_PORT: u16 = 4732;
-const PATH_TO_MESENGES_LOG: &str = "messenges.log";
-const PATH_TO_GENERAL_LOG: &str = "general.log";
+mod ws;
+use ws::WsTransport;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // init logger
-    let messenges = fern::log_file(PATH_TO_MESENGES_LOG)?;
-    let general = fern::log_file(PATH_TO_GENERAL_LOG)?;
-    utils::init_logger(messenges, general).expect("failed to initialize logger");
+/// maximum number of connections waiting to be accepted by a listener
+const LISTEN_BACKLOG: i32 = 1024;
 
-    // accepts several possible addresses
-    let matches = clap::App::new("Rustenger server")
+/// command line arguments of the server, every setting of the config can be given by them
+fn app() -> clap::App<'static, 'static> {
+    // accepts several addresses, the server listens on all of them
+    clap::App::new("Rustenger server")
         .version("0.0.0")
         .author("Aitzhanov Ivan <aitvann@gmail.com>")
         .about("Asynchronous server for Rustenger")
+        .arg(
+            clap::Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .env("RUSTENGER_CONFIG")
+                .takes_value(true)
+                .help("TOML config file, arguments take precedence over it"),
+        )
         .arg(
             clap::Arg::with_name("addresses")
                 .short("a")
                 .multiple(true)
                 .takes_value(true)
-                .help("address of server"),
+                .help("addresses of server, IPv4 and IPv6 ones can be mixed"),
+        )
+        .arg(
+            clap::Arg::with_name("cert")
+                .long("cert")
+                .env("RUSTENGER_CERT")
+                .takes_value(true)
+                .help("PEM file with TLS certificate chain, enables TLS"),
+        )
+        .arg(
+            clap::Arg::with_name("key")
+                .long("key")
+                .env("RUSTENGER_KEY")
+                .takes_value(true)
+                .help("PEM file with TLS private key"),
+        )
+        .arg(
+            clap::Arg::with_name("client-ca")
+                .long("client-ca")
+                .env("RUSTENGER_CLIENT_CA")
+                .takes_value(true)
+                .help(
+                    "PEM file with CA certificates, enables authentication by client certificates",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("crl")
+                .long("crl")
+                .env("RUSTENGER_CRL")
+                .takes_value(true)
+                .help("PEM or DER file with revoked client certificates"),
+        )
+        .arg(
+            clap::Arg::with_name("max-frame-size")
+                .long("max-frame-size")
+                .env("RUSTENGER_MAX_FRAME_SIZE")
+                .takes_value(true)
+                .help("maximum size of frame in bytes, clients sending larger frames are disconnected"),
+        )
+        .arg(
+            clap::Arg::with_name("ws")
+                .long("ws")
+                .multiple(true)
+                .takes_value(true)
+                .help("addresses of WebSocket listeners for browser clients, TLS is applied to them too"),
+        )
+        .arg(
+            clap::Arg::with_name("idle-timeout")
+                .long("idle-timeout")
+                .env("RUSTENGER_IDLE_TIMEOUT")
+                .takes_value(true)
+                .help("seconds after which clients sending nothing are disconnected"),
+        )
+        .arg(
+            clap::Arg::with_name("unix")
+                .long("unix")
+                .env("RUSTENGER_UNIX")
+                .takes_value(true)
+                .help("path of unix socket for local clients, they are logged in as their system user"),
+        )
+        .arg(
+            clap::Arg::with_name("unix-mode")
+                .long("unix-mode")
+                .env("RUSTENGER_UNIX_MODE")
+                .takes_value(true)
+                .help("octal permissions of the unix socket, 660 by default"),
+        )
+        .arg(
+            clap::Arg::with_name("admin")
+                .long("admin")
+                .env("RUSTENGER_ADMIN")
+                .takes_value(true)
+                .help("path of admin socket for 'rustenger-admin'"),
+        )
+        .arg(
+            clap::Arg::with_name("admin-mode")
+                .long("admin-mode")
+                .env("RUSTENGER_ADMIN_MODE")
+                .takes_value(true)
+                .help("octal permissions of the admin socket, 600 by default"),
+        )
+        .arg(
+            clap::Arg::with_name("log-level")
+                .long("log-level")
+                .env("RUSTENGER_LOG_LEVEL")
+                .takes_value(true)
+                .help("level of logs written to the general log file, info by default"),
         )
-        .get_matches();
-
-    // selects the first available address from the arguments
-    let mut addrs = matches.values_of("addresses");
-    let iter = addrs.iter_mut().flatten();
-    let mut listener = stream::iter(iter)
-        .filter_map(async move |a| a.parse().ok())
-        .chain(stream::once(future::ready(
-            (DEFAULT_ADDR, DEFAULT_PORT).into(),
-        )))
-        .filter_map(async move |a: SocketAddr| {
-            TcpListener::bind(a)
-                .await
-                .inspect_err(|e| log::warn!("failed to bind to address: {}; error: {}", a, e))
-                .ok()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = app().get_matches();
+
+    // the user is shown what is wrong with the config without debug formatting
+    let config = Config::load(&matches).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(1)
+    });
+
+    // init logger
+    let messenges = fern::log_file(&config.log.messages)?;
+    let general = fern::log_file(&config.log.general)?;
+    utils::init_logger(
+        messenges,
+        general,
+        config.log.level,
+        config.log.stdout_level,
+    )
+    .expect("failed to initialize logger");
+    if let Some(path) = matches.value_of("config") {
+        log::info!("config is read from: {}", path);
+    }
+
+    // TLS is enabled only if the certificate is given,
+    // clients are authenticated by certificates only if the CA is given
+    let tls = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => {
+            let acceptor = match &config.tls.client_ca {
+                Some(ca) => {
+                    let crl = config.tls.crl.as_deref();
+                    log::info!("client certificates are verified against: {}", ca.display());
+                    Acceptor::with_client_auth(cert, key, ca, crl)?
+                }
+                None => Acceptor::new(cert, key)?,
+            };
+
+            log::info!("TLS is enabled with certificate: {}", cert.display());
+            Some(acceptor)
+        }
+        _ => None,
+    };
+
+    // all addresses are bound, the server is available on those that succeed
+    let addrs = &config.listen;
+    let listeners = bind_all(&addrs.addresses, Protocol::Framed)?;
+    let ws_listeners = match addrs.ws.as_slice() {
+        [] => Vec::new(),
+        addrs => bind_all(addrs, Protocol::WebSocket)?,
+    };
+
+    let unix_listener = match &addrs.unix {
+        Some(path) => {
+            let listener = unix::bind(path, addrs.unix_mode)?;
+            log::info!(
+                "unix listener has successful bind to path: {}",
+                path.display()
+            );
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let admin_listener = match &addrs.admin {
+        Some(path) => {
+            let listener = unix::bind(path, addrs.admin_mode)?;
+            log::info!(
+                "admin listener has successful bind to path: {}",
+                path.display()
+            );
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let server = Server::new(&config);
+    let reloader = Reloader::new(matches, config.clone(), server.clone());
+
+    let framed = listeners.into_iter().map(|l| (l, Protocol::Framed));
+    let ws = ws_listeners.into_iter().map(|l| (l, Protocol::WebSocket));
+    let mut tasks: Vec<BoxFuture<()>> = framed
+        .chain(ws)
+        .map(|(l, protocol)| listen(l, tls.clone(), server.clone(), protocol).boxed())
+        .collect();
+    if let Some(unix_listener) = unix_listener {
+        tasks.push(listen_unix(unix_listener, server.clone()).boxed());
+    }
+    if let Some(admin_listener) = admin_listener {
+        tasks.push(admin::listen(admin_listener, reloader.clone()).boxed());
+    }
+    tasks.push(reload_on_hangup(reloader).boxed());
+
+    // listeners are dropped on shutdown, so new clients are not accepted
+    let signal = match future::select(future::join_all(tasks), shutdown_signal().boxed()).await {
+        Either::Left(_) => return Ok(()),
+        Either::Right((signal, _)) => signal,
+    };
+    log::info!("received {}", signal);
+
+    server.shutdown().await;
+    for path in [&config.listen.unix, &config.listen.admin]
+        .iter()
+        .copied()
+        .flatten()
+    {
+        remove_socket(path);
+    }
+    log::info!("server is stopped");
+    log::logger().flush();
+
+    Ok(())
+}
+
+/// resolves on SIGINT or SIGTERM with the name of the signal,
+/// never resolves if the signals can not be listened for
+async fn shutdown_signal() -> &'static str {
+    let signals = signal(SignalKind::interrupt()).and_then(|interrupt| {
+        let terminate = signal(SignalKind::terminate())?;
+        Ok((interrupt, terminate))
+    });
+    let (mut interrupt, mut terminate) = match signals {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("failed to listen for SIGINT and SIGTERM: {}", e);
+            return future::pending().await;
+        }
+    };
+
+    let signal = match future::select(interrupt.recv().boxed(), terminate.recv().boxed()).await {
+        Either::Left(_) => "SIGINT",
+        Either::Right(_) => "SIGTERM",
+    };
+    signal
+}
+
+/// removes the socket file, so the next run does not find it
+fn remove_socket(path: &Path) {
+    fs::remove_file(path)
+        .inspect_err(|e| log::warn!("failed to remove socket {}: {}", path.display(), e))
+        .ok();
+}
+
+/// reloads the config on SIGHUP and applies it to the running server
+async fn reload_on_hangup(reloader: Reloader) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::error!("failed to listen for SIGHUP, config is not reloaded: {}", e);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        log::info!("reload config on SIGHUP");
+        if let Err(e) = reloader.reload().await {
+            log::error!("failed to reload config, the old one is kept: {}", e);
+        }
+    }
+}
+
+/// binds the listener to 'addr', IPv6 listeners accept only IPv6 connections,
+/// so '[::]:P' and '0.0.0.0:P' can be bound together
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let domain = if addr.is_ipv6() {
+        Domain::ipv6()
+    } else {
+        Domain::ipv4()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(socket2::Protocol::tcp()))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+
+    TcpListener::from_std(socket.into_tcp_listener())
+}
+
+/// binds listeners to all 'addrs', failures are reported and skipped,
+/// fails only if none of the listeners is bound
+fn bind_all(addrs: &[SocketAddr], protocol: Protocol) -> io::Result<Vec<TcpListener>> {
+    let results = addrs.iter().map(|&addr| bind(addr));
+    let listeners: Vec<_> = addrs
+        .iter()
+        .zip(results)
+        .filter_map(|(addr, res)| match res {
+            Ok(listener) => {
+                log::info!(
+                    "{:?} listener has successful bind to address: {}",
+                    protocol,
+                    addr
+                );
+                Some(listener)
+            }
+            Err(e) => {
+                log::error!(
+                    "failed to bind {:?} listener to address: {}; error: {}",
+                    protocol,
+                    addr,
+                    e
+                );
+                None
+            }
         })
-        .boxed()
-        .next()
-        .await
-        .expect("failed to select listener address");
+        .collect();
 
-    log::info!(
-        "listener has successful bind to address: {}",
-        listener.local_addr().unwrap()
-    );
+    if listeners.is_empty() {
+        let msg = format!("failed to bind {:?} listener to any address", protocol);
+        return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, msg));
+    }
+    Ok(listeners)
+}
 
-    let server = Server::new();
+/// how messages are carried over accepted streams
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    /// length-delimited frames after the preface
+    Framed,
+    /// WebSocket messages
+    WebSocket,
+}
 
+/// accepts streams and processes each of them in a separate task
+async fn listen(
+    mut listener: TcpListener,
+    tls: Option<Acceptor>,
+    server: Server,
+    protocol: Protocol,
+) {
     let mut incoming = listener.incoming();
     while let Some(res) = incoming.next().await {
         if let Ok(stream) = res.inspect_err(|e| log::error!("failed to accept stream: {}", e)) {
-            tokio::spawn(process(stream, server.clone()));
+            tokio::spawn(process(stream, tls.clone(), server.clone(), protocol));
         }
     }
-
-    Ok(())
 }
 
-/// process the accepted stream
-async fn process(stream: TcpStream, server: Server) {
+/// process the accepted stream, performs TLS handshake if 'tls' is given
+async fn process(stream: TcpStream, tls: Option<Acceptor>, server: Server, protocol: Protocol) {
     if let Ok(addr) = stream
         .peer_addr()
         .inspect_err(|e| log::warn!("failed to get peer addr: {}", e))
@@ -86,19 +399,79 @@ async fn process(stream: TcpStream, server: Server) {
         log::info!("accept stream: {}", addr);
     }
 
-    if let Ok(client) = Client::new(stream, server)
-        .await
-        .inspect_err(|e| log::error!("failed to create 'Client': {}", e))
-    {
-        // if the user has not exit
-        if let Some(client) = client {
-            log::info!("succefull create 'Client'");
-
-            client
-                .run()
-                .await
-                .inspect_err(|e| log::error!("error while run 'Client': {}", e))
-                .ok();
+    // peers which never finish the handshake would hold the task and the socket
+    let (stream, username): (Box<dyn Stream>, _) = match tls {
+        Some(acceptor) => {
+            let idle_timeout = server.idle_timeout().await;
+            match time::timeout(idle_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(accepted)) => accepted,
+                Ok(Err(e)) => {
+                    log::error!("failed TLS handshake: {}", e);
+                    return;
+                }
+                Err(_) => {
+                    log::error!("TLS handshake is not finished in {:?}", idle_timeout);
+                    return;
+                }
+            }
         }
+        None => (Box::new(stream), None),
+    };
+
+    serve(stream, username, server, protocol).await
+}
+
+/// accepts local streams, peers are logged in by their credentials
+async fn listen_unix(mut listener: UnixListener, server: Server) {
+    let mut incoming = listener.incoming();
+    while let Some(res) = incoming.next().await {
+        if let Ok(stream) = res.inspect_err(|e| log::error!("failed to accept unix stream: {}", e))
+        {
+            let username = unix::peer_username(&stream);
+            log::info!("accept unix stream of user: {:?}", username);
+            let stream = Box::new(stream);
+            tokio::spawn(serve(stream, username, server.clone(), Protocol::Framed));
+        }
+    }
+}
+
+/// creates the client on the accepted stream and runs it,
+/// 'username' is given if the stream is already authenticated
+async fn serve(
+    stream: Box<dyn Stream>,
+    username: Option<Username>,
+    server: Server,
+    protocol: Protocol,
+) {
+    let client = match protocol {
+        Protocol::Framed => Client::new(stream, username, server).await,
+        Protocol::WebSocket => {
+            let idle_timeout = server.idle_timeout().await;
+            let accept = WsTransport::accept(stream, server.max_frame_size().await);
+            match time::timeout(idle_timeout, accept).await {
+                Ok(Ok(ws)) => Client::with_framed(Box::new(ws), username, server).await,
+                Ok(Err(e)) => {
+                    log::error!("failed WebSocket handshake: {}", e);
+                    return;
+                }
+                Err(_) => {
+                    log::error!("WebSocket handshake is not finished in {:?}", idle_timeout);
+                    return;
+                }
+            }
+        }
+    };
+
+    // if the user has not exit
+    if let Ok(Some(client)) =
+        client.inspect_err(|e| log::error!("failed to create 'Client': {}", e))
+    {
+        log::info!("succefull create 'Client'");
+
+        client
+            .run()
+            .await
+            .inspect_err(|e| log::error!("error while run 'Client': {}", e))
+            .ok();
     }
 }
@@ -1,55 +1,464 @@
 use crate::client::Client;
-use crate::utils::EntryExt;
+use crate::config::{Config, Limits};
+use crate::store::MessageStore;
+use crate::utils::{self, EntryExt};
 use chrono::Utc;
 use rustenger_shared::{
-    account::{Account, Username},
-    message::UserMessage,
+    account::{Account, Password, Presence, Status, StatusMessage, Username},
+    admin::RoomStats,
+    codec,
+    e2e::{self, EncryptedMessage, KeyBundle},
+    message::{
+        ClientMessage, DirectMessage, DirectStatus, Event, Mention, MessageId, OnlinePage,
+        Reaction, Receipt, Response, RoomInfo, SignInError, UserMessage,
+    },
+    scram::{self, Challenge, Credentials, Secret},
     RoomName,
 };
-use std::{collections::HashMap, future::Future, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    future::{self, Future},
+    result,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::{
+    sync::{broadcast, mpsc, watch, Mutex, RwLock},
+    time,
+};
 
-pub type RoomMsgTx = mpsc::Sender<Client>;
-pub type RoomMsgRx = mpsc::Receiver<Client>;
+pub type RoomMsgTx = mpsc::Sender<RoomMsg>;
+pub type RoomMsgRx = mpsc::Receiver<RoomMsg>;
+
+/// message from server to room
+#[derive(Debug)]
+pub enum RoomMsg {
+    /// client entering the room, boxed, so other messages do not take its size
+    Client(Box<Client>),
+    /// direct message to a client in the room
+    Direct(DirectMessage),
+    /// receipt of direct message to its sender in the room
+    Receipt(Username, Receipt),
+    /// disconnect of a client banned while it is in the room
+    Kick(Username),
+    /// disconnect of all clients and stop of the room, the server is shutting down
+    Shutdown,
+    /// announcement of the operator to all clients in the room
+    Announce(String),
+    /// move of all clients out of the room and stop of it, the room is deleted
+    Delete,
+}
+
+/// message from the server to clients outside rooms
+#[derive(Debug, Clone)]
+pub enum LobbyMsg {
+    /// disconnect of the client with the username
+    Kick(Username),
+    /// announcement of the operator
+    Announce(String),
+}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// number of accounts in one page of online list
+const ONLINE_PAGE_SIZE: usize = 32;
+
+/// number of messages to clients outside rooms queued for the slowest of them
+const LOBBY_CHANNEL_CAPACITY: usize = 64;
+
+/// how often the number of connected clients is checked while waiting for them on shutdown
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// number of direct messages waiting for receipts, receipts of the oldest ones are not reported
+const SENT_CAPACITY: usize = 4096;
+
+/// how long the kick waits for the room of the user to accept it
+const KICK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// minimum interval between relays of typing state of a client, whatever the state is
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("room '{0}' already exist")]
     RoomAlreadyExist(RoomName),
     #[error("room '{0}' does not exist")]
     RoomDoesNotExits(RoomName),
+    #[error("message '{0}' does not exist")]
+    MessageDoesNotExist(MessageId),
+    #[error("direct message is longer than {} bytes", e2e::MAX_CIPHERTEXT_LEN)]
+    DirectTooLong,
     #[error("send error: {0}")]
-    Send(#[from] mpsc::error::SendError<Client>),
-    #[error("bincode error: {0}")]
-    Bincode(#[from] bincode::Error),
+    Send(Box<mpsc::error::SendError<RoomMsg>>),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("codec error: {0}")]
+    Codec(#[from] codec::Error),
+    #[error("nothing is received for {0:?}")]
+    IdleTimeout(Duration),
+    #[error("connection is closed")]
+    Disconnected,
+    #[error("user '{0}' is not online")]
+    UserNotOnline(Username),
+    #[error("user '{0}' does not exist")]
+    UserDoesNotExist(Username),
+    #[error("room '{0}' is busy, try again later")]
+    RoomBusy(RoomName),
+}
+
+impl Error {
+    /// returns true if the connection with the client is lost or must be dropped,
+    /// so the client can not be read from anymore
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Self::Disconnected | Self::IdleTimeout(_) => true,
+            Self::Codec(codec::Error::Io(_)) => true,
+            // the rest of too large frame is left unread in the stream
+            Self::Codec(codec::Error::FrameTooLarge { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+/// id of the connection the user is signed in with
+pub type SessionId = u64;
+
+/// presence of the account with the connection it is reported by
+struct PresenceEntry {
+    session: SessionId,
+    presence: Presence,
+}
+
+/// direct message waiting for receipts
+struct Sent {
+    adresser: Username,
+    recipient: Username,
+    /// last status reported to the adresser
+    status: Option<DirectStatus>,
 }
 
 // for rooms it is used RwLock, because it is often used for reading
 // - access to ServerRoomMessageTx and rarely for writing - adding a new Room;
-// used Mutex for ServerRoomMessage because it is always used for writing
+// used Mutex for ServerRoomMessage because it is always used for writing;
+// mention inboxes are always used for writing too;
+// presence registry is used RwLock, because it is read on each request about the user;
+// accounts are used RwLock, because they are read on each log in and written on sign up;
+// key bundles are used RwLock, because they are fetched more often than published;
+// direct inboxes are always used for writing;
+// message counts are used RwLock, because they are read on each request of rooms list;
+// read markers, sent direct messages and receipt inboxes are always used for writing;
+// limits, message of the day and bans are used RwLock, because they are written only on reload;
+// the number of connected clients is atomic, because it is changed from Drop of the client
 /// A mediator between Rooms, contains links to each room and is accessible from each room
 #[derive(Clone)]
 pub struct Server {
     links: Arc<RwLock<HashMap<RoomName, Mutex<RoomMsgTx>>>>,
+    mentions: Arc<Mutex<HashMap<Username, VecDeque<Mention>>>>,
+    presence: Arc<RwLock<HashMap<Username, PresenceEntry>>>,
+    accounts: Arc<RwLock<HashMap<Username, Credentials>>>,
+    keys: Arc<RwLock<HashMap<Username, KeyBundle>>>,
+    directs: Arc<Mutex<HashMap<Username, VecDeque<DirectMessage>>>>,
+    /// number of messages in each room
+    counts: Arc<RwLock<HashMap<RoomName, u64>>>,
+    /// number of messages of each room read by the account
+    read_markers: Arc<Mutex<HashMap<Username, HashMap<RoomName, u64>>>>,
+    /// direct messages not read yet
+    sent: Arc<Mutex<BTreeMap<MessageId, Sent>>>,
+    receipts: Arc<Mutex<HashMap<Username, VecDeque<Receipt>>>>,
+    /// id of the next direct message
+    direct_id: Arc<AtomicU64>,
+    /// id of the next signed in connection
+    session_id: Arc<AtomicU64>,
+    limits: Arc<RwLock<Limits>>,
+    /// message of the day sent to users after sign in
+    motd: Arc<RwLock<Option<String>>>,
+    /// accounts which are not allowed to sign in by the config
+    bans: Arc<RwLock<HashSet<Username>>>,
+    /// accounts banned by the operator, they are kept on reload
+    runtime_bans: Arc<RwLock<HashSet<Username>>>,
+    /// number of signed in clients
+    connected: Arc<AtomicUsize>,
+    /// becomes 'true' when the server starts shutting down
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    shutdown_rx: watch::Receiver<bool>,
+    /// messages to clients outside rooms
+    lobby_tx: broadcast::Sender<LobbyMsg>,
+    /// secret challenges for unknown accounts are made up with
+    scram_secret: Secret,
 }
 
 impl Server {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         let raw_links = HashMap::<RoomName, Mutex<RoomMsgTx>>::new();
         let links = Arc::new(RwLock::new(raw_links));
-        Self { links }
+        let mentions = Arc::new(Mutex::new(HashMap::new()));
+        let presence = Arc::new(RwLock::new(HashMap::new()));
+        let accounts = Arc::new(RwLock::new(HashMap::new()));
+        let keys = Arc::new(RwLock::new(HashMap::new()));
+        let directs = Arc::new(Mutex::new(HashMap::new()));
+        let counts = Arc::new(RwLock::new(HashMap::new()));
+        let read_markers = Arc::new(Mutex::new(HashMap::new()));
+        let sent = Arc::new(Mutex::new(BTreeMap::new()));
+        let receipts = Arc::new(Mutex::new(HashMap::new()));
+        let direct_id = Arc::new(AtomicU64::new(0));
+        let session_id = Arc::new(AtomicU64::new(0));
+        let limits = Arc::new(RwLock::new(config.limits));
+        let motd = Arc::new(RwLock::new(config.motd.clone()));
+        let bans = Arc::new(RwLock::new(config.bans.iter().copied().collect()));
+        let runtime_bans = Arc::new(RwLock::new(HashSet::new()));
+        let connected = Arc::new(AtomicUsize::new(0));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let shutdown_tx = Arc::new(shutdown_tx);
+        let (lobby_tx, _) = broadcast::channel(LOBBY_CHANNEL_CAPACITY);
+        let scram_secret = scram::secret();
+        Self {
+            links,
+            mentions,
+            presence,
+            accounts,
+            keys,
+            directs,
+            counts,
+            read_markers,
+            sent,
+            receipts,
+            direct_id,
+            session_id,
+            limits,
+            motd,
+            bans,
+            runtime_bans,
+            connected,
+            shutdown_tx,
+            shutdown_rx,
+            lobby_tx,
+            scram_secret,
+        }
+    }
+
+    /// notifies rooms and clients outside rooms that the server is shutting down
+    /// and waits up to shutdown timeout for clients to disconnect
+    pub async fn shutdown(&self) {
+        let timeout = self.limits().await.shutdown_timeout();
+        log::info!(
+            "shutting down, waiting {:?} for clients to disconnect",
+            timeout
+        );
+
+        let wait = async {
+            if self.shutdown_tx.broadcast(true).is_err() {
+                log::error!("failed to notify clients about shutdown");
+            }
+
+            for (name, mut msg_tx) in self.room_links().await {
+                if msg_tx.send(RoomMsg::Shutdown).await.is_err() {
+                    log::warn!("room '{}' is already stopped", name);
+                }
+            }
+
+            while self.connected.load(Ordering::SeqCst) > 0 {
+                time::delay_for(SHUTDOWN_POLL_INTERVAL).await;
+            }
+        };
+
+        match time::timeout(timeout, wait).await {
+            Ok(()) => log::info!("all clients are disconnected"),
+            Err(_) => log::warn!(
+                "{} clients are still connected, they are dropped",
+                self.connected.load(Ordering::SeqCst)
+            ),
+        }
+    }
+
+    /// resolves when the server starts shutting down
+    pub async fn shutting_down(&self) {
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        while let Some(shutdown) = shutdown_rx.recv().await {
+            if shutdown {
+                return;
+            }
+        }
+        // the sender lives as long as the server, so it is not reached
+        future::pending().await
+    }
+
+    /// returns copies of links to all rooms, so rooms can be awaited without the lock
+    async fn room_links(&self) -> Vec<(RoomName, RoomMsgTx)> {
+        let mut links = Vec::new();
+        for (&name, msg_tx) in self.links.read().await.iter() {
+            links.push((name, msg_tx.lock().await.clone()));
+        }
+        links
+    }
+
+    /// subscribes the client outside rooms to messages of the server
+    pub fn lobby(&self) -> broadcast::Receiver<LobbyMsg> {
+        self.lobby_tx.subscribe()
+    }
+
+    /// disconnects the user wherever it is, fails if it is not online
+    pub async fn kick(&self, username: Username) -> Result<()> {
+        match self.presence(username).await {
+            Some(p) if p.status != Status::Offline => (),
+            _ => return Err(Error::UserNotOnline(username)),
+        }
+
+        // the user may be moving between a room and the lobby, so both are told
+        let res = self.kick_from_room(username).await;
+        self.lobby_tx.send(LobbyMsg::Kick(username)).ok();
+        res
+    }
+
+    /// passes the kick to the room of the user, unlike 'send_to_room' waits for the room,
+    /// so the kick is not lost if the room is busy; fails if it is busy for 'KICK_TIMEOUT'
+    async fn kick_from_room(&self, username: Username) -> Result<()> {
+        let room = match self.presence(username).await.and_then(|p| p.room) {
+            Some(room) => room,
+            None => {
+                log::debug!("user '{}' is not in a room", username);
+                return Ok(());
+            }
+        };
+        let mut msg_tx = match self.links.read().await.get(&room) {
+            Some(msg_tx) => msg_tx.lock().await.clone(),
+            None => return Ok(()),
+        };
+
+        time::timeout(KICK_TIMEOUT, msg_tx.send(RoomMsg::Kick(username)))
+            .await
+            .map_err(|_| Error::RoomBusy(room))?
+            .map_err(|e| Error::Send(Box::new(e)))
+    }
+
+    /// bans the account until restart and disconnects it if it is online,
+    /// fails if the account is banned but is not disconnected
+    pub async fn ban(&self, username: Username) -> Result<()> {
+        self.runtime_bans.write().await.insert(username);
+        match self.kick(username).await {
+            Err(Error::UserNotOnline(_)) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// allows the account to sign in again, returns false if it is not banned;
+    /// bans of the config are lifted until reload
+    pub async fn unban(&self, username: Username) -> bool {
+        let runtime = self.runtime_bans.write().await.remove(&username);
+        let config = self.bans.write().await.remove(&username);
+        runtime || config
+    }
+
+    /// sends the announcement of the operator to all signed in users
+    pub async fn announce(&self, text: String) {
+        for (name, mut msg_tx) in self.room_links().await {
+            if msg_tx.send(RoomMsg::Announce(text.clone())).await.is_err() {
+                log::warn!("room '{}' is already stopped", name);
+            }
+        }
+        self.lobby_tx.send(LobbyMsg::Announce(text)).ok();
+    }
+
+    /// deletes the room, its clients are moved out of it
+    pub async fn delete_room(&self, name: RoomName) -> Result<()> {
+        let mut msg_tx = match self.links.read().await.get(&name) {
+            Some(msg_tx) => msg_tx.lock().await.clone(),
+            None => return Err(Error::RoomDoesNotExits(name)),
+        };
+
+        msg_tx
+            .send(RoomMsg::Delete)
+            .await
+            .map_err(|e| Error::Send(Box::new(e)))
+    }
+
+    /// returns all rooms with the number of messages and the users in them, sorted by name
+    pub async fn room_stats(&self) -> Vec<RoomStats> {
+        let mut rooms: Vec<_> = self.links.read().await.keys().copied().collect();
+        rooms.sort();
+        let counts = self.counts.read().await;
+        let presences = self.presences().await;
+
+        rooms
+            .into_iter()
+            .map(|name| RoomStats {
+                name,
+                messages: counts.get(&name).copied().unwrap_or(0),
+                users: presences
+                    .iter()
+                    .filter(|p| p.room == Some(name))
+                    .map(|p| p.account.username())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// counts the signed in client, called on its creation
+    pub fn connect(&self) {
+        self.connected.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// uncounts the signed in client, called on its drop
+    pub fn disconnect(&self) {
+        self.connected.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// applies limits, message of the day and bans of the reloaded config,
+    /// banned users are disconnected, bans made by the operator are kept
+    pub async fn reload(&self, config: &Config) {
+        *self.limits.write().await = config.limits;
+        *self.motd.write().await = config.motd.clone();
+
+        let bans: HashSet<_> = config.bans.iter().copied().collect();
+        let banned: Vec<_> = bans.difference(&*self.bans.read().await).copied().collect();
+        *self.bans.write().await = bans;
+
+        for username in banned {
+            match self.kick(username).await {
+                Ok(()) | Err(Error::UserNotOnline(_)) => (),
+                Err(e) => log::warn!("failed to kick banned user '{}': {}", username, e),
+            }
+        }
+    }
+
+    /// returns current limits
+    pub async fn limits(&self) -> Limits {
+        *self.limits.read().await
+    }
+
+    /// returns message of the day
+    pub async fn motd(&self) -> Option<String> {
+        self.motd.read().await.clone()
+    }
+
+    /// checks whether the account is banned by the config or by the operator
+    pub async fn is_banned(&self, username: Username) -> bool {
+        self.bans.read().await.contains(&username)
+            || self.runtime_bans.read().await.contains(&username)
+    }
+
+    /// returns maximum size of frames sent and received by clients
+    pub async fn max_frame_size(&self) -> usize {
+        self.limits().await.max_frame_size
+    }
+
+    /// returns time after which silent clients are disconnected
+    pub async fn idle_timeout(&self) -> Duration {
+        self.limits().await.idle_timeout()
     }
 
     /// create link to room with name 'name'
     // pub async fn create_room(self, name: RoomName) -> Result<()> {
+    // the future type would be cyclic: it spawns the room which awaits clients which await it
+    #[allow(clippy::manual_async_fn)]
     pub fn create_room(self, name: RoomName) -> impl Future<Output = Result<()>> + Send {
         async move {
             log::info!("attempt to create new room '{}'", name);
 
-            let (msg_tx, msg_rx) = mpsc::channel(64);
+            let (msg_tx, msg_rx) = mpsc::channel(self.limits().await.room_channel_capacity);
             let mut lock = self.links.write().await;
             lock.entry(name)
                 .vacant()
@@ -72,6 +481,11 @@ impl Server {
             .occupied()
             .ok_or(Error::RoomDoesNotExits(name))?
             .remove();
+        self.counts.write().await.remove(&name);
+        // a new room with the same name starts unread
+        for markers in self.read_markers.lock().await.values_mut() {
+            markers.remove(&name);
+        }
 
         Ok(())
     }
@@ -89,21 +503,399 @@ impl Server {
             .get(&room_name)
             .ok_or(Error::RoomDoesNotExits(room_name))?;
 
+        let username = client.username();
         let mut msg_tx_lock = msg_tx.lock().await;
-        msg_tx_lock.send(client).await.map_err(Error::Send)
+        msg_tx_lock
+            .send(RoomMsg::Client(Box::new(client)))
+            .await
+            .map_err(|e| Error::Send(Box::new(e)))?;
+
+        self.set_room(username, Some(room_name)).await;
+        Ok(())
     }
 
     /// build 'Vec' of names of all rooms in the server
-    pub async fn rooms(&self) -> Vec<RoomName> {
-        self.links.read().await.keys().cloned().collect()
+    pub async fn rooms(&self, username: Username) -> Vec<RoomInfo> {
+        let names: Vec<_> = self.links.read().await.keys().cloned().collect();
+        let counts = self.counts.read().await;
+        let lock = self.read_markers.lock().await;
+        let markers = lock.get(&username);
+
+        names
+            .into_iter()
+            .map(|name| {
+                let count = counts.get(&name).copied().unwrap_or(0);
+                let read = markers.and_then(|m| m.get(&name)).copied().unwrap_or(0);
+                RoomInfo {
+                    name,
+                    unread: count.saturating_sub(read),
+                }
+            })
+            .collect()
+    }
+
+    /// counts new message 'id' of the room, it is read by its adresser
+    pub async fn posted(&self, room: RoomName, adresser: Username, id: MessageId) {
+        self.counts.write().await.insert(room, id + 1);
+        self.set_read(adresser, room, id + 1).await;
+    }
+
+    /// marks messages of the room up to and including 'id' as read by 'username'
+    pub async fn mark_read(&self, username: Username, room: RoomName, id: MessageId) -> Result<()> {
+        let count = self.counts.read().await.get(&room).copied().unwrap_or(0);
+        if id >= count {
+            return Err(Error::MessageDoesNotExist(id));
+        }
+
+        self.set_read(username, room, id + 1).await;
+        Ok(())
+    }
+
+    /// moves read marker forward, it never moves back
+    async fn set_read(&self, username: Username, room: RoomName, read: u64) {
+        let mut lock = self.read_markers.lock().await;
+        let marker = lock.entry(username).or_default().entry(room).or_insert(0);
+        *marker = read.max(*marker);
+    }
+
+    /// puts the mention into inbox of user 'username', mentions of unknown users are dropped,
+    /// so inboxes are not created for arbitrary words
+    pub async fn mention(&self, username: Username, mention: Mention) {
+        if !self.is_known(username).await {
+            return;
+        }
+
+        let mut lock = self.mentions.lock().await;
+        let inbox = lock.entry(username).or_default();
+        if inbox.len() == self.limits().await.mention_inbox_capacity {
+            inbox.pop_front();
+        }
+        inbox.push_back(mention);
+    }
+
+    /// publishes public keys of the account, replacing previous ones
+    pub async fn publish_keys(&self, username: Username, bundle: KeyBundle) {
+        log::info!(
+            "user '{}' published keys with fingerprint {}",
+            username,
+            bundle.fingerprint()
+        );
+
+        let mut lock = self.keys.write().await;
+        lock.insert(username, bundle);
+    }
+
+    /// returns public keys of the account
+    pub async fn keys(&self, username: Username) -> Option<KeyBundle> {
+        self.keys.read().await.get(&username).copied()
+    }
+
+    /// creates direct message from 'adresser' to 'recipient', the payload is kept opaque,
+    /// fails if the recipient is unknown, so inboxes are not created for arbitrary names
+    pub async fn direct_message(
+        &self,
+        adresser: Account,
+        recipient: Username,
+        payload: EncryptedMessage,
+    ) -> Result<DirectMessage> {
+        if payload.ciphertext.len() > e2e::MAX_CIPHERTEXT_LEN {
+            return Err(Error::DirectTooLong);
+        }
+        if !self.is_known(recipient).await {
+            return Err(Error::UserDoesNotExist(recipient));
+        }
+
+        let id = self.direct_id.fetch_add(1, Ordering::Relaxed);
+        let msg = DirectMessage {
+            id,
+            adresser,
+            recipient,
+            utc: Utc::now(),
+            payload,
+        };
+
+        let sent = Sent {
+            adresser: adresser.username(),
+            recipient,
+            status: None,
+        };
+        let mut lock = self.sent.lock().await;
+        lock.insert(id, sent);
+        // ids are increasing, so the first one is the oldest
+        if lock.len() > SENT_CAPACITY {
+            lock.pop_first();
+        }
+
+        Ok(msg)
+    }
+
+    /// sends the direct message to the room the recipient is in,
+    /// or puts it into the inbox of the recipient if it is not in a room;
+    /// never waits for the room, so rooms can deliver to each other without deadlocks
+    pub async fn deliver(&self, msg: DirectMessage) {
+        log::info!(
+            "direct message #{} from '{}' to '{}'",
+            msg.id,
+            msg.adresser.username(),
+            msg.recipient
+        );
+
+        if let Err(RoomMsg::Direct(msg)) =
+            self.send_to_room(msg.recipient, RoomMsg::Direct(msg)).await
+        {
+            self.store_direct(msg).await;
+        }
+    }
+
+    /// passes the message to the room of 'username' without waiting,
+    /// returns the message back if the user is not in a room or the room is busy
+    async fn send_to_room(&self, username: Username, msg: RoomMsg) -> result::Result<(), RoomMsg> {
+        let room = match self.presence(username).await.and_then(|p| p.room) {
+            Some(room) => room,
+            None => return Err(msg),
+        };
+
+        match self.links.read().await.get(&room) {
+            Some(msg_tx) => msg_tx.lock().await.try_send(msg).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(msg) | mpsc::error::TrySendError::Closed(msg) => {
+                    msg
+                }
+            }),
+            None => Err(msg),
+        }
+    }
+
+    /// reports that 'username' has received or read direct message 'id' to its sender,
+    /// fails if the message is not sent to 'username' or is already read
+    pub async fn receipt(
+        &self,
+        username: Username,
+        id: MessageId,
+        status: DirectStatus,
+    ) -> Result<()> {
+        let adresser = {
+            let mut lock = self.sent.lock().await;
+            let sent = lock
+                .get_mut(&id)
+                .filter(|sent| sent.recipient == username)
+                .ok_or(Error::MessageDoesNotExist(id))?;
+            if sent.status == Some(status) {
+                return Ok(());
+            }
+            sent.status = Some(status);
+
+            let adresser = sent.adresser;
+            if status == DirectStatus::Read {
+                lock.remove(&id);
+            }
+            adresser
+        };
+        log::info!("direct message #{} is {:?} by '{}'", id, status, username);
+
+        let receipt = Receipt {
+            id,
+            recipient: username,
+            status,
+        };
+        if let Err(RoomMsg::Receipt(_, receipt)) = self
+            .send_to_room(adresser, RoomMsg::Receipt(adresser, receipt))
+            .await
+        {
+            self.store_receipt(adresser, receipt).await;
+        }
+
+        Ok(())
+    }
+
+    /// puts the receipt into the inbox of 'username'
+    pub async fn store_receipt(&self, username: Username, receipt: Receipt) {
+        let mut lock = self.receipts.lock().await;
+        let inbox = lock.entry(username).or_default();
+        if inbox.len() == self.limits().await.receipt_inbox_capacity {
+            inbox.pop_front();
+        }
+        inbox.push_back(receipt);
+    }
+
+    /// takes all receipts from the inbox of 'username'
+    pub async fn take_receipts(&self, username: Username) -> Vec<Receipt> {
+        let mut lock = self.receipts.lock().await;
+        lock.remove(&username).map(Vec::from).unwrap_or_default()
+    }
+
+    /// puts the direct message into inbox of its recipient
+    pub async fn store_direct(&self, msg: DirectMessage) {
+        let mut lock = self.directs.lock().await;
+        let inbox = lock.entry(msg.recipient).or_default();
+        if inbox.len() == self.limits().await.direct_inbox_capacity {
+            inbox.pop_front();
+        }
+        inbox.push_back(msg);
+    }
+
+    /// takes all direct messages from inbox of user 'username'
+    pub async fn take_directs(&self, username: Username) -> Vec<DirectMessage> {
+        let mut lock = self.directs.lock().await;
+        lock.remove(&username).map(Vec::from).unwrap_or_default()
+    }
+
+    /// registers new account, only credentials derived from the password are stored
+    pub async fn register(
+        &self,
+        username: Username,
+        password: Password,
+    ) -> std::result::Result<(), SignInError> {
+        let credentials = utils::blocking(move || Credentials::new(&password)).await;
+
+        let mut lock = self.accounts.write().await;
+        lock.entry(username)
+            .vacant()
+            .ok_or(SignInError::UserNameAlreadyUsed)?
+            .insert(credentials);
+
+        Ok(())
+    }
+
+    /// returns credentials of the account
+    pub async fn credentials(&self, username: Username) -> Option<Credentials> {
+        self.accounts.read().await.get(&username).copied()
+    }
+
+    /// checks whether the user has an account or has ever signed in, users authenticated
+    /// by client certificates or peer credentials have presence, but no account
+    pub async fn is_known(&self, username: Username) -> bool {
+        self.presence.read().await.contains_key(&username)
+            || self.accounts.read().await.contains_key(&username)
+    }
+
+    /// makes up challenge for the account which does not exist,
+    /// it is indistinguishable from the challenge of an existing account
+    pub fn unknown_challenge(&self, username: Username) -> Challenge {
+        scram::unknown_challenge(&self.scram_secret, &username)
+    }
+
+    /// marks the account as online, called on sign in,
+    /// returns id of the session the presence belongs to
+    pub async fn online(&self, account: Account) -> SessionId {
+        log::info!("user '{}' is online", account.username());
+
+        let session = self.session_id.fetch_add(1, Ordering::Relaxed);
+        let presence = Presence {
+            account,
+            status: Status::Online,
+            message: StatusMessage::new(),
+            last_seen: Utc::now(),
+            room: None,
+        };
+        let mut lock = self.presence.write().await;
+        lock.insert(account.username(), PresenceEntry { session, presence });
+
+        session
+    }
+
+    /// marks the account as offline, called on disconnect,
+    /// nothing is changed if the user has signed in again with another session;
+    /// 'self' insted of '&self" due to this method used in Drop
+    pub async fn offline(self, username: Username, session: SessionId) {
+        let mut lock = self.presence.write().await;
+        let presence = match lock.get_mut(&username) {
+            Some(entry) if entry.session == session => &mut entry.presence,
+            _ => return,
+        };
+        log::info!("user '{}' is offline", username);
+
+        presence.status = Status::Offline;
+        presence.message = StatusMessage::new();
+        presence.last_seen = Utc::now();
+        presence.room = None;
+    }
+
+    /// sets status of the account with status message
+    pub async fn set_status(&self, username: Username, status: Status, message: StatusMessage) {
+        let mut lock = self.presence.write().await;
+        if let Some(PresenceEntry { presence, .. }) = lock.get_mut(&username) {
+            presence.status = status;
+            presence.message = message;
+            presence.last_seen = Utc::now();
+        }
+    }
+
+    /// sets the room the account is in
+    pub async fn set_room(&self, username: Username, room: Option<RoomName>) {
+        let mut lock = self.presence.write().await;
+        if let Some(PresenceEntry { presence, .. }) = lock.get_mut(&username) {
+            presence.room = room;
+        }
+    }
+
+    /// updates the account stored with presence, e.g. after change of color
+    pub async fn update_account(&self, account: Account) {
+        let mut lock = self.presence.write().await;
+        if let Some(PresenceEntry { presence, .. }) = lock.get_mut(&account.username()) {
+            presence.account = account;
+        }
+    }
+
+    /// returns presence of the account, 'None' if it has never been online
+    pub async fn presence(&self, username: Username) -> Option<Presence> {
+        self.presence.read().await.get(&username).map(|e| e.presence)
+    }
+
+    /// builds page 'page' of accounts which are not offline, sorted by username,
+    /// rooms of the accounts are not hidden, because every room is public
+    pub async fn online_page(&self, page: u32) -> OnlinePage {
+        let accounts = self.presences().await;
+        let pages = accounts.len().div_ceil(ONLINE_PAGE_SIZE);
+        let accounts = accounts
+            .chunks(ONLINE_PAGE_SIZE)
+            .nth(page as usize)
+            .map(<[_]>::to_vec)
+            .unwrap_or_default();
+
+        OnlinePage {
+            page,
+            pages: pages as u32,
+            accounts,
+        }
+    }
+
+    /// returns presences of accounts which are not offline, sorted by username
+    pub async fn presences(&self) -> Vec<Presence> {
+        let mut presences = self
+            .presence
+            .read()
+            .await
+            .values()
+            .map(|e| e.presence)
+            .filter(|p| p.status != Status::Offline)
+            .collect::<Vec<_>>();
+        presences.sort_by_key(|p| p.account.username());
+        presences
+    }
+
+    /// takes all mentions from inbox of user 'username'
+    pub async fn take_mentions(&self, username: Username) -> Vec<Mention> {
+        let mut lock = self.mentions.lock().await;
+        lock.remove(&username).map(Vec::from).unwrap_or_default()
     }
 }
 
 pub type Clients = HashMap<Username, Option<Client>>;
 
+/// what the room has waited for
+enum Update {
+    /// message from the server, 'None' if the room is removed
+    Msg(Option<RoomMsg>),
+    /// message from the client or error of reading it
+    Read(Account, Result<ClientMessage>),
+}
+
 pub struct Room {
     name: RoomName,
     clients: Clients,
+    /// time of the last relay of typing state of clients
+    typing: HashMap<Username, Instant>,
+    store: MessageStore,
     msg_rx: RoomMsgRx,
     server: Server,
 }
@@ -112,9 +904,13 @@ impl Room {
     /// creates new room without links with other rooms
     fn new(name: RoomName, msg_rx: RoomMsgRx, server: Server) -> Self {
         let clients = HashMap::new();
+        let typing = HashMap::new();
+        let store = MessageStore::new();
         Self {
             name,
             clients,
+            typing,
+            store,
             msg_rx,
             server,
         }
@@ -125,67 +921,174 @@ impl Room {
         log::info!("run room: {}", self.name());
 
         loop {
-            self.accept_client();
-
-            if self.clients.is_empty() {
-                // yield the current task to allow to accept
-                // another clients if work in single thread
-                tokio::task::yield_now().await;
-                continue;
+            match self.next().await {
+                Update::Msg(Some(RoomMsg::Shutdown)) => {
+                    self.shutdown().await;
+                    break;
+                }
+                Update::Msg(Some(RoomMsg::Delete)) => {
+                    self.delete().await;
+                    break;
+                }
+                Update::Msg(Some(msg)) => self.accept(msg).await,
+                Update::Msg(None) => break,
+                Update::Read(adresser, res) => self.update(adresser, res).await,
             }
-
-            self.update().await;
         }
     }
 
-    /// check if new client is avaliable and accpet it
-    fn accept_client(&mut self) {
-        use futures::future;
-
-        let recv = future::maybe_done(self.msg_rx.recv());
-        futures::pin_mut!(recv);
-        if let Some(client) = recv.as_mut().take_output() {
-            let client = client.unwrap();
-            let username = client.username();
+    /// waits for a message from the server or from any of the clients
+    async fn next(&mut self) -> Update {
+        use futures::future::{self, Either, FutureExt};
 
-            self.clients.insert(username, Some(client));
-            log::info!(
-                "accepted client with name '{}' to room '{}'",
-                username,
-                self.name
-            );
+        let recv = self.msg_rx.recv().map(Update::Msg);
+        if self.clients.is_empty() {
+            return recv.await;
         }
-    }
-
-    /// selects clients messages and handles them
-    async fn update(&mut self) {
-        use futures::future::{self, FutureExt};
-        use rustenger_shared::message::ClientMessage;
 
-        let iter = self.clients.values_mut().map(|client| {
+        let reads = self.clients.values_mut().map(|client| {
             let client = client.as_mut().unwrap();
             let adresser = client.account();
 
-            client.read().map(move |m| (adresser, m)).boxed()
+            client
+                .read()
+                .map(move |res| Update::Read(adresser, res))
+                .boxed()
         });
-        let (adresser, res) = future::select_all(iter).await.0;
+        let read = future::select_all(reads).map(|(update, ..)| update);
+
+        futures::pin_mut!(recv);
+        match future::select(recv, read).await {
+            Either::Left((update, _)) | Either::Right((update, _)) => update,
+        }
+    }
+
+    /// accepts new client, delivers direct message to a client or disconnects it
+    async fn accept(&mut self, msg: RoomMsg) {
+        match msg {
+            RoomMsg::Client(client) => {
+                let username = client.username();
+
+                self.clients.insert(username, Some(*client));
+                log::info!(
+                    "accepted client with name '{}' to room '{}'",
+                    username,
+                    self.name
+                );
+            }
+            // the server has routed the message here, so it is not passed back to the server,
+            // otherwise it could bounce between rooms while the presence is stale
+            RoomMsg::Direct(msg) => {
+                if let Some(msg) = self.direct(msg).await {
+                    self.server.store_direct(msg).await;
+                }
+            }
+            RoomMsg::Receipt(username, receipt) => self.receipt(username, receipt).await,
+            RoomMsg::Shutdown | RoomMsg::Delete => unreachable!("the room is stopped before it"),
+            RoomMsg::Announce(text) => {
+                if let Err(e) = self.notify(Event::Announcement(text), None).await {
+                    log::error!("failed to announce: {}", e);
+                }
+            }
+            RoomMsg::Kick(username) => {
+                if let Some(Some(client)) = self.clients.get(&username) {
+                    log::info!(
+                        "disconnect banned user '{}' from room '{}'",
+                        username,
+                        self.name
+                    );
+                    let account = client.account();
+                    self.leave(account).await;
+                }
+            }
+        }
+    }
+
+    /// handles message of the client
+    async fn update(&mut self, adresser: Account, res: Result<ClientMessage>) {
+        use rustenger_shared::message::Command;
 
         match res {
+            Err(Error::Disconnected) => {
+                log::info!("user '{}' disconnected", adresser.username());
+                self.leave(adresser).await;
+            }
+            Err(e) if e.is_disconnect() => {
+                log::warn!("disconnect '{}': {}", adresser.username(), e);
+                self.leave(adresser).await;
+            }
             Err(e) => log::error!("failed to recieve client message: {}", e),
-            Ok(ClientMessage::UserMessage(msg)) => {
-                if let Err(e) = self.broadcast(adresser, msg).await {
-                    log::error!("failed to broadcast user message: {}", e);
+            // pings are answered by the client itself
+            Ok(ClientMessage::Ping) => (),
+            Ok(ClientMessage::Ack(id)) => {
+                let username = adresser.username();
+                if let Err(e) = self
+                    .server
+                    .receipt(username, id, DirectStatus::Delivered)
+                    .await
+                {
+                    log::warn!("failed to acknowledge direct message: {}", e);
+                }
+            }
+            Ok(ClientMessage::UserMessage(msg, parent)) => {
+                match self.broadcast(adresser, *msg, parent).await {
+                    Err(Error::MessageDoesNotExist(id)) => self.unknown_message(adresser, id).await,
+                    Err(e) => log::error!("failed to broadcast user message: {}", e),
+                    Ok(()) => (),
+                }
+            }
+            Ok(ClientMessage::Typing(typing)) => {
+                if let Err(e) = self.typing(adresser, typing).await {
+                    log::error!("failed to relay typing: {}", e);
+                }
+            }
+            Ok(ClientMessage::Direct(recipient, payload)) => {
+                match self
+                    .server
+                    .direct_message(adresser, recipient, payload)
+                    .await
+                {
+                    Err(Error::UserDoesNotExist(un)) => self.unknown_user(adresser, un).await,
+                    Err(e) => log::error!("failed to send direct message: {}", e),
+                    Ok(msg) => {
+                        if let Some(msg) = self.direct(msg).await {
+                            self.server.deliver(msg).await;
+                        }
+                    }
+                }
+            }
+            Ok(ClientMessage::Command(Command::Thread(id))) => {
+                match self.thread(adresser, id).await {
+                    Err(Error::MessageDoesNotExist(id)) => self.unknown_message(adresser, id).await,
+                    Err(e) => log::error!("failed to send thread: {}", e),
+                    Ok(()) => (),
+                }
+            }
+            Ok(ClientMessage::Command(Command::React(id, reaction))) => {
+                match self.react(adresser, id, reaction, true).await {
+                    Err(Error::MessageDoesNotExist(id)) => self.unknown_message(adresser, id).await,
+                    Err(e) => log::error!("failed to add reaction: {}", e),
+                    Ok(()) => (),
+                }
+            }
+            Ok(ClientMessage::Command(Command::Unreact(id, reaction))) => {
+                match self.react(adresser, id, reaction, false).await {
+                    Err(Error::MessageDoesNotExist(id)) => self.unknown_message(adresser, id).await,
+                    Err(e) => log::error!("failed to remove reaction: {}", e),
+                    Ok(()) => (),
                 }
             }
             Ok(ClientMessage::Command(cmd)) => {
                 let mut entry = self.clients.entry(adresser.username()).occupied().unwrap();
                 let client = entry.get_mut().take().unwrap();
 
+                // the client is consumed by the handler, so it is gone on error as well
                 match client.handle(cmd).await {
-                    Err(e) => log::error!("failed to handle command: {}", e),
-                    Ok(None) => {
-                        entry.remove();
+                    Err(e) => {
+                        log::error!("failed to handle command: {}", e);
+                        self.leave(adresser).await;
                     }
+                    Ok(None) => self.leave(adresser).await,
                     Ok(Some(client)) => {
                         *entry.get_mut() = Some(client);
                     }
@@ -194,15 +1097,71 @@ impl Room {
         }
     }
 
-    /// sends messages to all clients except 'account'
-    async fn broadcast(&mut self, adresser: Account, text: UserMessage) -> Result<()> {
-        use rustenger_shared::message::{AccountMessage, ServerMessage};
+    /// notifies all clients about shutdown and disconnects them
+    async fn shutdown(&mut self) {
+        log::info!("shut down room '{}'", self.name);
 
-        let msg = AccountMessage {
-            text,
-            adresser,
-            utc: Utc::now(),
-        };
+        for (username, client) in self.clients.drain() {
+            if let Some(mut client) = client {
+                if let Err(e) = client.shutdown().await {
+                    log::warn!("failed to notify '{}' about shutdown: {}", username, e);
+                }
+            }
+        }
+    }
+
+    /// notifies all clients about deletion and moves them out of the room
+    async fn delete(&mut self) {
+        use rustenger_shared::message::ServerMessage;
+
+        log::info!("delete room '{}'", self.name);
+
+        for (username, client) in self.clients.drain() {
+            if let Some(mut client) = client {
+                let event = Event::RoomDeleted(self.name);
+                if let Err(e) = client.write(ServerMessage::Event(event)).await {
+                    log::warn!("failed to notify '{}' about deletion: {}", username, e);
+                }
+                if let Err(e) = client.exit_room().await {
+                    log::error!("failed to move '{}' out of room: {}", username, e);
+                }
+            }
+        }
+    }
+
+    /// removes the client and notifies others that it has left
+    async fn leave(&mut self, account: Account) {
+        self.clients.remove(&account.username());
+        self.typing.remove(&account.username());
+
+        if let Err(e) = self.notify(Event::Leave(account), None).await {
+            log::error!("failed to notify about leaving: {}", e);
+        }
+    }
+
+    /// stores the message and sends it to all clients except 'account',
+    /// mentioned users get the message flagged and put into their mention inboxes
+    async fn broadcast(
+        &mut self,
+        adresser: Account,
+        text: UserMessage,
+        parent: Option<MessageId>,
+    ) -> Result<()> {
+        use rustenger_shared::message::ServerMessage;
+
+        let msg = self.store.push(adresser, text, parent)?;
+        self.server
+            .posted(self.name, adresser.username(), msg.id)
+            .await;
+        let mentioned = utils::mentions(&text);
+
+        for &username in mentioned.iter().filter(|&&un| un != adresser.username()) {
+            let mention = Mention {
+                room: self.name,
+                message: msg.clone(),
+            };
+            self.server.mention(username, mention).await;
+        }
 
         for client in self
             .clients
@@ -210,12 +1169,164 @@ impl Room {
             .map(|c| c.as_mut().unwrap())
             .filter(|c| c.username() != adresser.username())
         {
-            client.write(ServerMessage::AccountMessage(msg)).await?;
+            let mut msg = msg.clone();
+            msg.mentioned = mentioned.contains(&client.username());
+            // the client which can not be written to is evicted when reading from it fails
+            if let Err(e) = client
+                .write(ServerMessage::AccountMessage(Box::new(msg)))
+                .await
+            {
+                log::warn!("failed to write to '{}': {}", client.username(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// adds or removes reaction of 'adresser' and notifies all clients about it
+    async fn react(
+        &mut self,
+        adresser: Account,
+        id: MessageId,
+        reaction: Reaction,
+        added: bool,
+    ) -> Result<()> {
+        use rustenger_shared::message::ReactionEvent;
+
+        let count = self.store.react(id, reaction, adresser.username(), added)?;
+        if let Some(count) = count {
+            let event = ReactionEvent {
+                id,
+                reaction,
+                adresser,
+                added,
+                count,
+            };
+            self.notify(Event::Reaction(event), None).await?;
         }
 
         Ok(())
     }
 
+    /// relays typing state of 'adresser' to other clients not more often than 'TYPING_INTERVAL',
+    /// so alternating states are limited as well as repeated ones
+    async fn typing(&mut self, adresser: Account, typing: bool) -> Result<()> {
+        let now = Instant::now();
+        let username = adresser.username();
+
+        if let Some(&time) = self.typing.get(&username) {
+            if now.duration_since(time) < TYPING_INTERVAL {
+                return Ok(());
+            }
+        }
+        self.typing.insert(username, now);
+
+        self.notify(Event::Typing(adresser, typing), Some(username))
+            .await
+    }
+
+    /// writes the direct message to the recipient if it is in the room,
+    /// otherwise returns the message back
+    async fn direct(&mut self, msg: DirectMessage) -> Option<DirectMessage> {
+        use rustenger_shared::message::ServerMessage;
+
+        match self
+            .clients
+            .get_mut(&msg.recipient)
+            .and_then(Option::as_mut)
+        {
+            Some(client) => {
+                if let Err(e) = client.write(ServerMessage::Direct(msg)).await {
+                    log::error!("failed to write direct message: {}", e);
+                }
+                None
+            }
+            None => Some(msg),
+        }
+    }
+
+    /// writes the receipt to its recipient if it is in the room, otherwise stores it
+    async fn receipt(&mut self, username: Username, receipt: Receipt) {
+        use rustenger_shared::message::ServerMessage;
+
+        match self.clients.get_mut(&username).and_then(Option::as_mut) {
+            Some(client) => {
+                if let Err(e) = client.write(ServerMessage::Receipt(receipt)).await {
+                    log::error!("failed to write receipt: {}", e);
+                }
+            }
+            None => self.server.store_receipt(username, receipt).await,
+        }
+    }
+
+    /// sends event to all clients except 'except'
+    async fn notify(&mut self, event: Event, except: Option<Username>) -> Result<()> {
+        use rustenger_shared::message::ServerMessage;
+
+        for client in self
+            .clients
+            .values_mut()
+            .map(|c| c.as_mut().unwrap())
+            .filter(|c| Some(c.username()) != except)
+        {
+            // the client which can not be written to is evicted when reading from it fails
+            if let Err(e) = client.write(ServerMessage::Event(event.clone())).await {
+                log::warn!("failed to write to '{}': {}", client.username(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// tells 'adresser' that message 'id' its message or command refers to does not exist
+    async fn unknown_message(&mut self, adresser: Account, id: MessageId) {
+        log::warn!(
+            "user '{}' refers to unknown message #{}",
+            adresser.username(),
+            id
+        );
+        self.respond(adresser, Response::UnknownMessage(id)).await
+    }
+
+    /// tells 'adresser' that user 'username' its message refers to does not exist
+    async fn unknown_user(&mut self, adresser: Account, username: Username) {
+        log::warn!(
+            "user '{}' refers to unknown user '{}'",
+            adresser.username(),
+            username
+        );
+        self.respond(adresser, Response::UnknownUser(username)).await
+    }
+
+    /// writes the response to 'adresser', which is in the room
+    async fn respond(&mut self, adresser: Account, response: Response) {
+        use rustenger_shared::message::ServerMessage;
+
+        let client = self
+            .clients
+            .get_mut(&adresser.username())
+            .and_then(Option::as_mut)
+            .unwrap();
+        if let Err(e) = client.write(ServerMessage::Response(response)).await {
+            log::warn!("failed to write to '{}': {}", adresser.username(), e);
+        }
+    }
+
+    /// sends the thread containing message 'id' to 'adresser'
+    async fn thread(&mut self, adresser: Account, id: MessageId) -> Result<()> {
+        use rustenger_shared::message::{Response, ServerMessage};
+
+        let thread = self.store.thread(id)?;
+        let response = Response::Thread(thread);
+
+        let client = self
+            .clients
+            .get_mut(&adresser.username())
+            .and_then(Option::as_mut)
+            .unwrap();
+        client.write(ServerMessage::Response(response)).await
+    }
+
     pub fn name(&self) -> RoomName {
         self.name
     }
@@ -226,6 +1337,241 @@ impl Drop for Room {
         log::debug!("drop the room: {}", self.name());
 
         let fut = self.server.clone().revome_room(self.name());
-        tokio::spawn(fut);
+        utils::spawn_cleanup(fut);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, connect, enter_room, recv, send};
+    use rustenger_shared::message::ServerMessage;
+
+    fn payload() -> EncryptedMessage {
+        EncryptedMessage {
+            ephemeral: [0; 32],
+            nonce: [0; 12],
+            ciphertext: vec![1, 2, 3],
+        }
+    }
+
+    #[tokio::test]
+    async fn offline_of_old_session_keeps_new_one() {
+        let server = Server::new(&Config::default());
+        let account = Account::new(Username::from("alice").unwrap());
+
+        let old = server.online(account).await;
+        let new = server.online(account).await;
+        server.clone().offline(account.username(), old).await;
+        let presence = server.presence(account.username()).await.unwrap();
+        assert_eq!(presence.status, Status::Online);
+
+        server.clone().offline(account.username(), new).await;
+        let presence = server.presence(account.username()).await.unwrap();
+        assert_eq!(presence.status, Status::Offline);
+    }
+
+    #[tokio::test]
+    async fn operator_bans_are_kept_on_reload() {
+        let mallory = Username::from("mallory").unwrap();
+        let eve = Username::from("eve").unwrap();
+        let config = Config {
+            bans: vec![eve],
+            ..Config::default()
+        };
+        let server = Server::new(&config);
+
+        server.ban(mallory).await.unwrap();
+        server.reload(&Config::default()).await;
+        assert!(server.is_banned(mallory).await);
+        assert!(!server.is_banned(eve).await);
+
+        assert!(server.unban(mallory).await);
+        assert!(!server.is_banned(mallory).await);
+        assert!(!server.unban(mallory).await);
+    }
+
+    #[tokio::test]
+    async fn direct_message_reaches_another_room() {
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "first").await;
+        enter_room(&server, &mut bob, "bob", "second").await;
+
+        let bob_name = testing::username("bob");
+        send(&mut alice, ClientMessage::Direct(bob_name, payload())).await;
+        match recv(&mut bob).await {
+            ServerMessage::Direct(msg) => {
+                assert_eq!(msg.adresser.username(), testing::username("alice"));
+                assert_eq!(msg.payload.ciphertext, payload().ciphertext);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        assert!(server.take_directs(bob_name).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn client_failed_to_get_motd_is_disconnected() {
+        let config = Config {
+            motd: Some("too long for one frame".repeat(8)),
+            limits: Limits {
+                max_frame_size: 64,
+                ..Limits::default()
+            },
+            ..Config::default()
+        };
+        let server = Server::new(&config);
+        let _alice = connect(&server, "alice").await;
+
+        let alice = testing::username("alice");
+        testing::wait_until(|| async {
+            let offline = server.presence(alice).await.map(|p| p.status) == Some(Status::Offline);
+            offline && server.connected.load(Ordering::SeqCst) == 0
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn authenticated_users_get_mentions() {
+        use rustenger_shared::message::Command;
+
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "main").await;
+
+        // mentions are stored in order, so the ghost is handled when bob has the mention
+        let text = UserMessage::from("@ghost @bob hi").unwrap();
+        send(&mut alice, ClientMessage::UserMessage(Box::new(text), None)).await;
+        let bob_name = testing::username("bob");
+        testing::wait_until(|| async { server.mentions.lock().await.contains_key(&bob_name) })
+            .await;
+
+        send(&mut bob, ClientMessage::Command(Command::Mentions)).await;
+        match recv(&mut bob).await {
+            ServerMessage::Response(Response::Mentions(mentions)) => assert_eq!(mentions.len(), 1),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        let ghost = testing::username("ghost");
+        assert!(!server.mentions.lock().await.contains_key(&ghost));
+    }
+
+    #[tokio::test]
+    async fn direct_message_to_unknown_user_is_refused() {
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "main").await;
+
+        let ghost = testing::username("ghost");
+        for client in [&mut alice, &mut bob].iter_mut() {
+            send(client, ClientMessage::Direct(ghost, payload())).await;
+            match recv(client).await {
+                ServerMessage::Response(Response::UnknownUser(un)) => assert_eq!(un, ghost),
+                msg => panic!("unexpected message: {:?}", msg),
+            }
+        }
+        assert!(!server.directs.lock().await.contains_key(&ghost));
+    }
+
+    /// config of tests waiting for idle clients to be evicted
+    fn short_idle_timeout() -> Config {
+        Config {
+            limits: Limits {
+                idle_timeout: 1,
+                ..Limits::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered_in_lobby_and_room() {
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+
+        send(&mut alice, ClientMessage::Ping).await;
+        assert!(matches!(recv(&mut alice).await, ServerMessage::Pong));
+
+        enter_room(&server, &mut alice, "alice", "main").await;
+        send(&mut alice, ClientMessage::Ping).await;
+        assert!(matches!(recv(&mut alice).await, ServerMessage::Pong));
+    }
+
+    #[tokio::test]
+    async fn silent_client_leaves_room_on_idle_timeout() {
+        let server = Server::new(&short_idle_timeout());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "main").await;
+        enter_room(&server, &mut bob, "bob", "main").await;
+
+        match testing::recv_alive(&mut bob).await {
+            ServerMessage::Event(Event::Leave(account)) => {
+                assert_eq!(account.username(), testing::username("alice"))
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        assert!(testing::closed(&mut alice).await);
+
+        let bob = testing::username("bob");
+        testing::wait_until(|| async { server.room_stats().await[0].users == vec![bob] }).await;
+    }
+
+    #[tokio::test]
+    async fn silent_client_is_evicted_from_lobby() {
+        let server = Server::new(&short_idle_timeout());
+        let mut alice = connect(&server, "alice").await;
+
+        assert!(testing::closed(&mut alice).await);
+        let alice = testing::username("alice");
+        testing::wait_until(|| async {
+            server.presence(alice).await.map(|p| p.status) == Some(Status::Offline)
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn disconnected_client_is_removed_from_room() {
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "main").await;
+        enter_room(&server, &mut bob, "bob", "main").await;
+
+        drop(alice);
+        match recv(&mut bob).await {
+            ServerMessage::Event(Event::Leave(account)) => {
+                assert_eq!(account.username(), testing::username("alice"))
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        // the presence is updated by the dropped client in a separate task
+        let bob = testing::username("bob");
+        testing::wait_until(|| async { server.room_stats().await[0].users == vec![bob] }).await;
+        assert_eq!(server.connected.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_notifies_clients_and_waits_for_them() {
+        let server = Server::new(&Config::default());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        enter_room(&server, &mut alice, "alice", "main").await;
+
+        let start = time::Instant::now();
+        server.shutdown().await;
+        assert!(start.elapsed() < testing::TIMEOUT);
+        assert_eq!(server.connected.load(Ordering::SeqCst), 0);
+
+        for client in [&mut alice, &mut bob].iter_mut() {
+            match recv(client).await {
+                ServerMessage::Event(Event::Shutdown) => (),
+                msg => panic!("unexpected message: {:?}", msg),
+            }
+            assert!(testing::closed(client).await);
+        }
     }
 }
@@ -0,0 +1,188 @@
+use crate::room::{Error, Result};
+use chrono::Utc;
+use rustenger_shared::{
+    account::{Account, Username},
+    message::{AccountMessage, MessageId, Reaction, UserMessage},
+};
+use std::collections::{HashMap, HashSet};
+
+/// in-memory history of the messages of a room,
+/// the id of a message is its index in the history
+#[derive(Default)]
+pub struct MessageStore {
+    messages: Vec<AccountMessage>,
+    replies: HashMap<MessageId, Vec<MessageId>>,
+    reactors: HashMap<(MessageId, Reaction), HashSet<Username>>,
+}
+
+impl MessageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// stores new message and returns it,
+    /// fails if the message it replies to does not exist
+    pub fn push(
+        &mut self,
+        adresser: Account,
+        text: UserMessage,
+        parent: Option<MessageId>,
+    ) -> Result<AccountMessage> {
+        if let Some(parent) = parent {
+            self.get(parent).ok_or(Error::MessageDoesNotExist(parent))?;
+        }
+
+        let id = self.messages.len() as MessageId;
+        let msg = AccountMessage {
+            id,
+            parent,
+            text,
+            adresser,
+            utc: Utc::now(),
+            reactions: Vec::new(),
+            mentioned: false,
+        };
+
+        if let Some(parent) = parent {
+            self.replies.entry(parent).or_default().push(id);
+        }
+        self.messages.push(msg.clone());
+
+        Ok(msg)
+    }
+
+    /// adds or removes reaction of 'username' to message 'id'
+    /// and returns the new number of such reactions, or 'None' if nothing changed
+    pub fn react(
+        &mut self,
+        id: MessageId,
+        reaction: Reaction,
+        username: Username,
+        added: bool,
+    ) -> Result<Option<u32>> {
+        let msg = self
+            .messages
+            .get_mut(id as usize)
+            .ok_or(Error::MessageDoesNotExist(id))?;
+
+        let reactors = self.reactors.entry((id, reaction)).or_default();
+        let changed = if added {
+            reactors.insert(username)
+        } else {
+            reactors.remove(&username)
+        };
+        if !changed {
+            return Ok(None);
+        }
+
+        let count = reactors.len() as u32;
+        if count == 0 {
+            self.reactors.remove(&(id, reaction));
+        }
+
+        match msg.reactions.iter().position(|(r, _)| *r == reaction) {
+            Some(pos) if count == 0 => {
+                msg.reactions.remove(pos);
+            }
+            Some(pos) => msg.reactions[pos].1 = count,
+            None => msg.reactions.push((reaction, count)),
+        }
+
+        Ok(Some(count))
+    }
+
+    /// returns message with id 'id'
+    pub fn get(&self, id: MessageId) -> Option<&AccountMessage> {
+        self.messages.get(id as usize)
+    }
+
+    /// collects the whole thread containing message 'id', starting from its root;
+    /// each reply follows its parent
+    pub fn thread(&self, id: MessageId) -> Result<Vec<AccountMessage>> {
+        let mut root = self.get(id).ok_or(Error::MessageDoesNotExist(id))?;
+        while let Some(parent) = root.parent.and_then(|p| self.get(p)) {
+            root = parent;
+        }
+
+        let mut thread = Vec::new();
+        let mut stack = vec![root.id];
+        while let Some(id) = stack.pop() {
+            thread.push(self.messages[id as usize].clone());
+            if let Some(replies) = self.replies.get(&id) {
+                stack.extend(replies.iter().rev());
+            }
+        }
+
+        Ok(thread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(name: &str) -> Account {
+        Account::new(Username::from(name).unwrap())
+    }
+
+    fn text(text: &str) -> UserMessage {
+        UserMessage::from(text).unwrap()
+    }
+
+    fn ids(thread: &[AccountMessage]) -> Vec<MessageId> {
+        thread.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn thread_starts_from_root_and_replies_follow_parents() {
+        let mut store = MessageStore::new();
+        let alice = account("alice");
+        let root = store.push(alice, text("root"), None).unwrap().id;
+        let first = store.push(alice, text("first"), Some(root)).unwrap().id;
+        let other = store.push(alice, text("other"), None).unwrap().id;
+        let second = store.push(alice, text("second"), Some(root)).unwrap().id;
+        let nested = store.push(alice, text("nested"), Some(first)).unwrap().id;
+
+        let expected = vec![root, first, nested, second];
+        assert_eq!(ids(&store.thread(nested).unwrap()), expected);
+        assert_eq!(ids(&store.thread(root).unwrap()), expected);
+        assert_eq!(ids(&store.thread(other).unwrap()), vec![other]);
+    }
+
+    #[test]
+    fn reply_to_unknown_message_is_rejected() {
+        let mut store = MessageStore::new();
+        let res = store.push(account("alice"), text("reply"), Some(7));
+
+        assert!(matches!(res, Err(Error::MessageDoesNotExist(7))));
+        assert!(store.get(0).is_none());
+        assert!(matches!(
+            store.thread(7),
+            Err(Error::MessageDoesNotExist(7))
+        ));
+    }
+
+    #[test]
+    fn reactions_are_toggled_per_user() {
+        let mut store = MessageStore::new();
+        let id = store.push(account("alice"), text("hi"), None).unwrap().id;
+        let like = Reaction::from("+1").unwrap();
+        let (bob, carol) = (
+            Username::from("bob").unwrap(),
+            Username::from("carol").unwrap(),
+        );
+
+        assert_eq!(store.react(id, like, bob, true).unwrap(), Some(1));
+        assert_eq!(store.react(id, like, bob, true).unwrap(), None);
+        assert_eq!(store.react(id, like, carol, true).unwrap(), Some(2));
+        assert_eq!(store.get(id).unwrap().reactions, vec![(like, 2)]);
+
+        assert_eq!(store.react(id, like, bob, false).unwrap(), Some(1));
+        assert_eq!(store.react(id, like, bob, false).unwrap(), None);
+        assert_eq!(store.react(id, like, carol, false).unwrap(), Some(0));
+        assert!(store.get(id).unwrap().reactions.is_empty());
+
+        let res = store.react(id + 1, like, bob, true);
+        assert!(matches!(res, Err(Error::MessageDoesNotExist(_))));
+    }
+}
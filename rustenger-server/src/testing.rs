@@ -0,0 +1,121 @@
+// Helpers of tests running clients against the server in the same process:
+//     - clients are connected through unix stream pairs, so no ports are bound
+//     - streams are served the same way as accepted ones, see 'crate::serve'
+//     - users are authenticated in advance as by client certificates, so they skip sign in
+use crate::{room::Server, Protocol};
+use futures::{Future, SinkExt, StreamExt};
+use rustenger_shared::{
+    account::Username,
+    codec::{ClientCodec, Preface},
+    message::{ClientMessage, Command, Response, ServerMessage},
+    RoomName,
+};
+use std::time::Duration;
+use tokio::{
+    io::AsyncWriteExt,
+    net::UnixStream,
+    time::{self, Instant},
+};
+use tokio_util::codec::Framed;
+
+/// how long tests wait for the server
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// how often conditions are checked while waiting for them
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// how often clients waiting in 'recv_alive' ping the server, shorter than idle timeouts of tests
+const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(200);
+
+pub type TestClient = Framed<UnixStream, ClientCodec>;
+
+pub fn username(name: &str) -> Username {
+    Username::from(name).unwrap()
+}
+
+pub fn room_name(name: &str) -> RoomName {
+    RoomName::from(name).unwrap()
+}
+
+/// serves one end of new stream pair and returns the other end,
+/// 'name' is given if the user is authenticated in advance
+pub fn serve(server: &Server, name: Option<&str>, protocol: Protocol) -> UnixStream {
+    let (stream, served) = UnixStream::pair().unwrap();
+    let username = name.map(username);
+    tokio::spawn(crate::serve(Box::new(served), username, server.clone(), protocol));
+    stream
+}
+
+/// opens framed connection to the server, 'name' is given if the user is authenticated in advance
+pub async fn open(server: &Server, name: Option<&str>) -> TestClient {
+    let mut stream = serve(server, name, Protocol::Framed);
+    stream.write_all(&Preface::DEFAULT.to_bytes()).await.unwrap();
+    Framed::new(stream, ClientCodec::new())
+}
+
+/// connects user 'name' to the server and waits for its sign in
+pub async fn connect(server: &Server, name: &str) -> TestClient {
+    let mut client = open(server, Some(name)).await;
+    match recv(&mut client).await {
+        ServerMessage::Response(Response::SignInResult(Ok(()))) => client,
+        msg => panic!("unexpected message instead of sign in result: {:?}", msg),
+    }
+}
+
+/// receives the next message, panics if the server is silent for 'TIMEOUT'
+pub async fn recv(client: &mut TestClient) -> ServerMessage {
+    time::timeout(TIMEOUT, client.next())
+        .await
+        .expect("the server has not answered")
+        .expect("the server has closed the connection")
+        .unwrap()
+}
+
+/// receives the next message except 'Pong' and pings the server meanwhile,
+/// so the client is not evicted while others are
+pub async fn recv_alive(client: &mut TestClient) -> ServerMessage {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        assert!(Instant::now() < deadline, "the server has not answered");
+        send(client, ClientMessage::Ping).await;
+        match time::timeout(KEEPALIVE_INTERVAL, client.next()).await {
+            Ok(Some(Ok(ServerMessage::Pong))) | Err(_) => (),
+            Ok(res) => return res.expect("the server has closed the connection").unwrap(),
+        }
+    }
+}
+
+/// returns true if the server closes the connection in 'TIMEOUT', messages before it are skipped
+pub async fn closed(client: &mut TestClient) -> bool {
+    let end = async {
+        while let Some(Ok(_)) = client.next().await {}
+    };
+    time::timeout(TIMEOUT, end).await.is_ok()
+}
+
+pub async fn send(client: &mut TestClient, msg: ClientMessage) {
+    client.send(msg).await.unwrap()
+}
+
+/// checks 'condition' until it is true, panics if it is not for 'TIMEOUT'
+pub async fn wait_until<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition().await {
+        assert!(Instant::now() < deadline, "the condition is not met");
+        time::delay_for(POLL_INTERVAL).await;
+    }
+}
+
+/// creates room 'name' if it does not exist and moves user 'user' of 'client' into it
+pub async fn enter_room(server: &Server, client: &mut TestClient, user: &str, name: &str) {
+    let name = room_name(name);
+    server.clone().create_room(name).await.ok();
+    send(client, ClientMessage::Command(Command::SelectRoom(name))).await;
+
+    let user = username(user);
+    wait_until(|| async { server.presence(user).await.and_then(|p| p.room) == Some(name) }).await;
+}
@@ -0,0 +1,78 @@
+use crate::utils::Stream;
+use rustenger_shared::{
+    account::Username,
+    tls::{self, RevocationList},
+};
+use std::{io, path::Path, result, sync::Arc};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_rustls::{rustls::Session, TlsAcceptor};
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("client certificate is revoked")]
+    Revoked,
+    #[error("client certificate has no valid username in common name")]
+    InvalidUsername,
+}
+
+/// performs TLS handshakes and authenticates clients by their certificates
+#[derive(Clone)]
+pub struct Acceptor {
+    acceptor: TlsAcceptor,
+    revoked: Arc<RevocationList>,
+}
+
+impl Acceptor {
+    /// creates acceptor with the certificate chain and the private key from PEM files
+    pub fn new(cert: &Path, key: &Path) -> tls::Result<Self> {
+        let config = tls::server_config(cert, key)?;
+        let acceptor = TlsAcceptor::from(config);
+        let revoked = Arc::new(RevocationList::default());
+        Ok(Self { acceptor, revoked })
+    }
+
+    /// creates acceptor which also verifies client certificates against CA certificates
+    /// from PEM file 'client_ca' and rejects certificates revoked by CRL file 'crl'
+    pub fn with_client_auth(
+        cert: &Path,
+        key: &Path,
+        client_ca: &Path,
+        crl: Option<&Path>,
+    ) -> tls::Result<Self> {
+        let config = tls::server_config_with_client_auth(cert, key, client_ca)?;
+        let acceptor = TlsAcceptor::from(config);
+        let revoked = match crl {
+            Some(crl) => RevocationList::load(crl)?,
+            None => RevocationList::default(),
+        };
+        let revoked = Arc::new(revoked);
+        Ok(Self { acceptor, revoked })
+    }
+
+    /// performs TLS handshake, returns the stream and the username from common name
+    /// of the verified client certificate, if the client has presented it
+    pub async fn accept(&self, stream: TcpStream) -> Result<(Box<dyn Stream>, Option<Username>)> {
+        let stream = self.acceptor.accept(stream).await?;
+
+        let username = match stream.get_ref().1.get_peer_certificates() {
+            Some(certs) => {
+                let cert = certs.first().ok_or(Error::InvalidUsername)?;
+                if self.revoked.is_revoked(cert) {
+                    return Err(Error::Revoked);
+                }
+
+                let cn = tls::common_name(cert).ok_or(Error::InvalidUsername)?;
+                let username = Username::from(&cn).map_err(|_| Error::InvalidUsername)?;
+                Some(username)
+            }
+            None => None,
+        };
+
+        Ok((Box::new(stream), username))
+    }
+}
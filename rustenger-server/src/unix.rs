@@ -0,0 +1,98 @@
+// Unix domain socket listener for bots and tools running on the same host:
+//     - access is restricted by permissions of the socket file
+//     - peers are logged in as the system user owning the connecting process
+use rustenger_shared::account::Username;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+};
+use tokio::net::{UnixListener, UnixStream};
+
+/// database of system users
+const PATH_TO_PASSWD: &str = "/etc/passwd";
+
+/// binds the listener to 'path' and sets permissions of the socket file to 'mode',
+/// the socket file left by the previous run is removed
+pub fn bind(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            let msg = format!("{} exists and is not a socket", path.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// returns the name of the system user owning the peer process,
+/// 'None' if it is unknown or is not a valid username
+pub fn peer_username(stream: &UnixStream) -> Option<Username> {
+    let uid = stream
+        .peer_cred()
+        .inspect_err(|e| log::warn!("failed to get peer credentials: {}", e))
+        .ok()?
+        .uid;
+
+    let passwd = fs::read_to_string(PATH_TO_PASSWD)
+        .inspect_err(|e| log::warn!("failed to read {}: {}", PATH_TO_PASSWD, e))
+        .ok()?;
+    let name = user_name(&passwd, uid);
+    if name.is_none() {
+        log::warn!("no system user with uid: {}", uid);
+    }
+
+    name.and_then(|name| Username::from(name).ok())
+}
+
+/// finds the name of the user with 'uid' in the content of passwd file,
+/// each line of it is 'name:password:uid:gid:...', lines without a name are skipped
+fn user_name(passwd: &str, uid: u32) -> Option<&str> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?.parse::<u32>().ok()?;
+        (id == uid && !name.is_empty()).then_some(name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/bash
+broken
+nouid:x
+badid:x:abc:100::/:/bin/sh
+alice:x:1000:1000:Alice:/home/alice:/bin/bash
+
+bob:x:1001:1001::/home/bob:/bin/sh
+";
+
+    #[test]
+    fn finds_user_by_uid() {
+        assert_eq!(user_name(PASSWD, 0), Some("root"));
+        assert_eq!(user_name(PASSWD, 1000), Some("alice"));
+        assert_eq!(user_name(PASSWD, 1001), Some("bob"));
+    }
+
+    #[test]
+    fn missing_uid_is_not_found() {
+        assert_eq!(user_name(PASSWD, 1002), None);
+        assert_eq!(user_name("", 0), None);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        // the group id is not taken for the user id
+        assert_eq!(user_name(PASSWD, 100), None);
+        assert_eq!(user_name("broken\nnouid:x\n:x:7:7::/:/bin/sh", 7), None);
+    }
+}
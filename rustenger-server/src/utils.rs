@@ -1,21 +1,81 @@
 use crate::room::{Error, Result};
-use futures::stream::StreamExt;
+use futures::{
+    sink::{Sink, SinkExt},
+    stream::StreamExt,
+};
 use rustenger_shared::{
-    codec::ServerCodec,
-    message::{ClientMessage, Command},
+    account::Username,
+    codec,
+    message::{ClientMessage, ServerMessage},
 };
 use std::{
     collections::hash_map::{Entry, OccupiedEntry, VacantEntry},
+    future::Future,
     result,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    runtime::Handle,
+    task,
+    time::{self, Instant},
 };
-use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
+
+/// stream the client is connected through, e.g. plain TCP or TLS over TCP
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+/// connection with the client carrying whole messages, e.g. framed stream or WebSocket
+pub trait Transport:
+    futures::Stream<Item = result::Result<ClientMessage, codec::Error>>
+    + Sink<ServerMessage, Error = codec::Error>
+    + Send
+    + Unpin
+{
+}
+
+impl<T> Transport for T where
+    T: futures::Stream<Item = result::Result<ClientMessage, codec::Error>>
+        + Sink<ServerMessage, Error = codec::Error>
+        + Send
+        + Unpin
+{
+}
+
+pub type ServerFramed = Box<dyn Transport>;
+
+/// level of logs written to the general log file, changed on reload
+static GENERAL_LEVEL: AtomicUsize = AtomicUsize::new(log::LevelFilter::Info as usize);
+
+/// level of logs written to stdout, changed on reload
+static STDOUT_LEVEL: AtomicUsize = AtomicUsize::new(log::LevelFilter::Trace as usize);
+
+/// sets levels of logs written to the general log file and to stdout
+pub fn set_log_levels(general: log::LevelFilter, stdout: log::LevelFilter) {
+    GENERAL_LEVEL.store(general as usize, Ordering::Relaxed);
+    STDOUT_LEVEL.store(stdout as usize, Ordering::Relaxed);
+}
+
+/// loads level stored by 'set_log_levels'
+fn log_level(level: &AtomicUsize) -> log::LevelFilter {
+    use log::LevelFilter::*;
+
+    [Off, Error, Warn, Info, Debug, Trace][level.load(Ordering::Relaxed)]
+}
 
 /// initializes the logger as follows:
 ///     - user messenged -> 'messages'
-///     - logs level Info without user messenges -> 'general'
-///     - all logs without user messenges -> stdout
-pub fn init_logger<O1, O2>(messages: O1, general: O2) -> result::Result<(), log::SetLoggerError>
+///     - logs of 'level' without user messenges -> 'general'
+///     - logs of 'stdout_level' without user messenges -> stdout
+/// levels can be changed later by 'set_log_levels'
+pub fn init_logger<O1, O2>(
+    messages: O1,
+    general: O2,
+    level: log::LevelFilter,
+    stdout_level: log::LevelFilter,
+) -> result::Result<(), log::SetLoggerError>
 where
     O1: Into<fern::Output>,
     O2: Into<fern::Output>,
@@ -28,6 +88,8 @@ where
     use log::LevelFilter;
     use std::io;
 
+    set_log_levels(level, stdout_level);
+
     let colors = ColoredLevelConfig::new()
         .error(Color::Red)
         .warn(Color::Yellow)
@@ -60,8 +122,8 @@ where
                         msg,
                     ));
                 })
-                .level(LevelFilter::Info)
-                .filter(|md| md.target() != "messenges")
+                .level(LevelFilter::Trace)
+                .filter(|md| md.target() != "messenges" && md.level() <= log_level(&GENERAL_LEVEL))
                 .chain(general),
         )
         .chain(
@@ -76,7 +138,7 @@ where
                     ));
                 })
                 .level(LevelFilter::Trace)
-                .filter(|md| md.target() != "messenges")
+                .filter(|md| md.target() != "messenges" && md.level() <= log_level(&STDOUT_LEVEL))
                 .chain(io::stdout()),
         )
         .apply()?;
@@ -84,16 +146,67 @@ where
     Ok(())
 }
 
-/// read from framed stream
-pub async fn framed_read(framed: &mut Framed<TcpStream, ServerCodec>) -> Result<ClientMessage> {
-    framed
-        .next()
+/// read from framed stream, answers pings; fails with 'Error::Disconnected' at the end of stream
+/// and if nothing is read during 'idle_timeout' since 'last_read', which is updated on each read
+pub async fn framed_read(
+    framed: &mut ServerFramed,
+    last_read: &mut Instant,
+    idle_timeout: Duration,
+) -> Result<ClientMessage> {
+    loop {
+        let msg = time::timeout_at(*last_read + idle_timeout, framed.next())
+            .await
+            .map_err(|_| Error::IdleTimeout(idle_timeout))?
+            .ok_or(Error::Disconnected)??;
+        *last_read = Instant::now();
+
+        match msg {
+            ClientMessage::Ping => framed.send(ServerMessage::Pong).await?,
+            msg => return Ok(msg),
+        }
+    }
+}
+
+/// runs CPU-heavy 'f', e.g. key derivation, on the blocking pool, so rooms are not stalled
+pub async fn blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(f)
         .await
-        .unwrap_or_else(|| {
-            log::error!("failed to read from framed");
-            Ok(ClientMessage::Command(Command::Exit))
-        })
-        .map_err(Error::Bincode)
+        .expect("blocking task does not panic")
+}
+
+/// spawns the cleanup of dropped 'Client' or 'Room', the cleanup is skipped
+/// if they are dropped by the runtime itself, which is stopped then
+pub fn spawn_cleanup<F>(cleanup: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Ok(handle) = Handle::try_current() {
+        handle.spawn(cleanup);
+    }
+}
+
+/// finds usernames mentioned in the text as '@username', without repetitions,
+/// '@' starts a mention only at the start of a word, so e-mail addresses are not mentions
+pub fn mentions(text: &str) -> Vec<Username> {
+    let mut usernames = Vec::new();
+
+    for word in text.split_whitespace().filter_map(|w| w.strip_prefix('@')) {
+        let end = word
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(word.len());
+        if let Ok(username) = Username::from(&word[..end]) {
+            if !username.is_empty() && !usernames.contains(&username) {
+                usernames.push(username);
+            }
+        }
+    }
+
+    usernames
 }
 
 /// transforms the `Entry<'a, K, V>` into a `Option<Occupiedentry<'a, K, V>` or into a `Option<VacantEntry<'a, K, V>`,
@@ -118,35 +231,35 @@ impl<'a, K, V> EntryExt<'a, K, V> for Entry<'a, K, V> {
     }
 }
 
-pub trait ResultExt<T, E> {
-    fn inspect<F>(self, f: F) -> Self
-    where
-        F: FnOnce(&T);
-    fn inspect_err<F>(self, f: F) -> Self
-    where
-        F: FnOnce(&E);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl<T, E> ResultExt<T, E> for result::Result<T, E> {
-    fn inspect<F>(self, f: F) -> Self
-    where
-        F: FnOnce(&T),
-    {
-        if let Ok(ref o) = self {
-            (f)(o);
-        }
+    fn usernames(names: &[&str]) -> Vec<Username> {
+        names.iter().map(|n| Username::from(n).unwrap()).collect()
+    }
 
-        self
+    #[test]
+    fn finds_mentions_at_start_of_words() {
+        let text = "@alice look, @bob-2 and @carol_x!";
+        assert_eq!(mentions(text), usernames(&["alice", "bob-2", "carol_x"]));
     }
 
-    fn inspect_err<F>(self, f: F) -> Self
-    where
-        F: FnOnce(&E),
-    {
-        if let Err(ref e) = self {
-            (f)(e);
-        }
+    #[test]
+    fn skips_repeated_mentions() {
+        assert_eq!(mentions("@alice @bob @alice"), usernames(&["alice", "bob"]));
+    }
+
+    #[test]
+    fn ignores_at_inside_words() {
+        assert!(mentions("write to user@example.com").is_empty());
+        assert!(mentions("a@b@c").is_empty());
+    }
 
-        self
+    #[test]
+    fn ignores_bare_at_and_too_long_names() {
+        let long = format!("@{}", "x".repeat(33));
+        assert!(mentions("@ @@ @!").is_empty());
+        assert!(mentions(&long).is_empty());
     }
 }
@@ -0,0 +1,197 @@
+// WebSocket transport for browsers and other clients which can not open raw TCP connections:
+//     - each WebSocket message carries one client or server message without the frame head
+//     - the format is chosen by subprotocol, JSON is used if the client asks for none
+//     - JSON messages are sent as text, bincode ones as binary, both are accepted
+use crate::utils::Stream;
+use futures::{ready, Sink};
+use rustenger_shared::{
+    codec::{self, Format, ServerCodec},
+    message::{ClientMessage, ServerMessage},
+};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{Request, Response},
+        http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL},
+        protocol::WebSocketConfig,
+        Error as WsError, Message,
+    },
+    WebSocketStream,
+};
+
+/// subprotocol of JSON messages
+pub const JSON_PROTOCOL: &str = "rustenger.json";
+
+/// subprotocol of bincode messages
+pub const BINCODE_PROTOCOL: &str = "rustenger.bincode";
+
+/// WebSocket connection carrying client and server messages
+pub struct WsTransport {
+    ws: WebSocketStream<Box<dyn Stream>>,
+    codec: ServerCodec,
+}
+
+impl WsTransport {
+    /// performs WebSocket handshake, messages larger than 'max_frame_size' are rejected
+    pub async fn accept(stream: Box<dyn Stream>, max_frame_size: usize) -> Result<Self, WsError> {
+        let mut format = Format::Json;
+        // the error type is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let callback = |request: &Request, mut response: Response| {
+            let protocols = request
+                .headers()
+                .get(SEC_WEBSOCKET_PROTOCOL)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            let protocol = protocols.split(',').map(str::trim).find_map(|p| match p {
+                JSON_PROTOCOL => Some((Format::Json, JSON_PROTOCOL)),
+                BINCODE_PROTOCOL => Some((Format::Bincode, BINCODE_PROTOCOL)),
+                _ => None,
+            });
+
+            if let Some((chosen, protocol)) = protocol {
+                format = chosen;
+                let value = HeaderValue::from_static(protocol);
+                response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+            }
+            Ok(response)
+        };
+
+        let config = WebSocketConfig {
+            max_message_size: Some(max_frame_size),
+            max_frame_size: Some(max_frame_size),
+            ..WebSocketConfig::default()
+        };
+        let ws =
+            tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(config)).await?;
+        log::debug!("websocket format: {:?}", format);
+
+        let codec = ServerCodec::with_format(format).max_frame_size(max_frame_size);
+        Ok(Self { ws, codec })
+    }
+}
+
+/// converts WebSocket error to the error of the codec, so that transports are interchangeable
+fn ws_error(e: WsError) -> codec::Error {
+    match e {
+        WsError::Io(e) => codec::Error::Io(e),
+        e => codec::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+impl futures::Stream for WsTransport {
+    type Item = Result<ClientMessage, codec::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let body = match ready!(Pin::new(&mut self.ws).poll_next(cx)) {
+                Some(Ok(Message::Binary(body))) => body,
+                Some(Ok(Message::Text(text))) => text.into_bytes(),
+                // pings are answered by the WebSocket itself
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(None),
+                Some(Err(e)) => return Poll::Ready(Some(Err(ws_error(e)))),
+            };
+
+            return Poll::Ready(Some(self.codec.decode_body(&body)));
+        }
+    }
+}
+
+impl Sink<ServerMessage> for WsTransport {
+    type Error = codec::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.ws).poll_ready(cx).map_err(ws_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ServerMessage) -> Result<(), Self::Error> {
+        let body = self.codec.encode_body(item)?;
+        let msg = match self.codec.format() {
+            Format::Bincode => Message::Binary(body),
+            Format::Json => Message::Text(String::from_utf8(body).expect("JSON is valid UTF-8")),
+        };
+
+        Pin::new(&mut self.ws).start_send(msg).map_err(ws_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.ws).poll_flush(cx).map_err(ws_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.ws).poll_close(cx).map_err(ws_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, room::Server, testing, Protocol};
+    use futures::{SinkExt, StreamExt};
+    use rustenger_shared::{codec::ClientCodec, message::Response};
+    use tokio::{net::UnixStream, time};
+    use tokio_tungstenite::tungstenite::http;
+
+    /// receives the next server message carried by WebSocket message of the format
+    async fn recv(ws: &mut WebSocketStream<UnixStream>, format: Format) -> ServerMessage {
+        let msg = time::timeout(testing::TIMEOUT, ws.next())
+            .await
+            .expect("the server has not answered")
+            .expect("the server has closed the connection")
+            .unwrap();
+        let body = match (format, msg) {
+            (Format::Json, Message::Text(text)) => text.into_bytes(),
+            (Format::Bincode, Message::Binary(body)) => body,
+            (_, msg) => panic!("unexpected message: {:?}", msg),
+        };
+        ClientCodec::with_format(format).decode_body(&body).unwrap()
+    }
+
+    /// connects user 'name' by WebSocket offering 'protocol' and waits for its sign in
+    async fn connect(server: &Server, name: &str, protocol: &str) -> WebSocketStream<UnixStream> {
+        let stream = testing::serve(server, Some(name), Protocol::WebSocket);
+        let request = http::Request::builder()
+            .uri("ws://localhost/")
+            .header(SEC_WEBSOCKET_PROTOCOL, protocol)
+            .body(())
+            .unwrap();
+        let (mut ws, response) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .unwrap();
+
+        let format = match response.headers().get(SEC_WEBSOCKET_PROTOCOL) {
+            Some(value) if value == BINCODE_PROTOCOL => Format::Bincode,
+            _ => Format::Json,
+        };
+        match recv(&mut ws, format).await {
+            ServerMessage::Response(Response::SignInResult(Ok(()))) => ws,
+            msg => panic!("unexpected message instead of sign in result: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn json_is_used_by_default_and_sent_as_text() {
+        let server = Server::new(&Config::default());
+        let mut ws = connect(&server, "alice", "unknown").await;
+
+        ws.send(Message::Text("\"Ping\"".into())).await.unwrap();
+        assert!(matches!(recv(&mut ws, Format::Json).await, ServerMessage::Pong));
+    }
+
+    #[tokio::test]
+    async fn bincode_is_chosen_by_subprotocol() {
+        let server = Server::new(&Config::default());
+        let mut ws = connect(&server, "alice", BINCODE_PROTOCOL).await;
+
+        let ping = ClientCodec::with_format(Format::Bincode)
+            .encode_body(ClientMessage::Ping)
+            .unwrap();
+        ws.send(Message::Binary(ping)).await.unwrap();
+        assert!(matches!(recv(&mut ws, Format::Bincode).await, ServerMessage::Pong));
+    }
+}
@@ -1,10 +1,13 @@
+use crate::RoomName;
 use arrayvec::ArrayString;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use thiserror::Error;
 
 pub type Username = ArrayString<[u8; 32]>;
 pub type Password = ArrayString<[u8; 32]>;
+pub type StatusMessage = ArrayString<[u8; 64]>;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Account {
@@ -75,3 +78,40 @@ impl FromStr for Color {
 #[derive(Error, Debug)]
 #[error("invalid color name")]
 pub struct ParseColorError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    Online,
+    Away,
+    Offline,
+}
+
+impl FromStr for Status {
+    type Err = ParseStatusError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let status = match src {
+            "Online" => Self::Online,
+            "Away" => Self::Away,
+            "Offline" => Self::Offline,
+            _ => return Err(ParseStatusError),
+        };
+
+        Ok(status)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid status name")]
+pub struct ParseStatusError;
+
+/// status of an account, 'last_seen' is time of the last change of status
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Presence {
+    pub account: Account,
+    pub status: Status,
+    pub message: StatusMessage,
+    pub last_seen: DateTime<Utc>,
+    /// room the account is in, 'None' if it is in the lobby or offline
+    pub room: Option<RoomName>,
+}
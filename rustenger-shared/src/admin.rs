@@ -0,0 +1,57 @@
+// Protocol of the admin socket of the server:
+//     - unix socket, access is restricted by permissions of the socket file
+//     - JSON frames without preface, each request is answered by one response
+//     - several requests can be sent over one connection
+use crate::{
+    account::{Presence, Username},
+    codec::Codec,
+    RoomName,
+};
+use serde::{Deserialize, Serialize};
+
+/// codec of the admin socket on the server side
+pub type AdminServerCodec = Codec<AdminRequest, AdminResponse>;
+
+/// codec of the admin socket on the operator side
+pub type AdminClientCodec = Codec<AdminResponse, AdminRequest>;
+
+/// command of the operator to the running server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminRequest {
+    /// lists rooms with their users
+    Rooms,
+    /// lists accounts which are not offline
+    Users,
+    /// disconnects the user, it can sign in again
+    Kick(Username),
+    /// disconnects the user and refuses its sign in until reload or restart
+    Ban(Username),
+    /// allows the banned user to sign in again
+    Unban(Username),
+    /// sends the announcement to every signed in user
+    Announce(String),
+    /// deletes the room, its users are moved out of it
+    DeleteRoom(RoomName),
+    /// reloads the config as on SIGHUP
+    Reload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    Rooms(Vec<RoomStats>),
+    Users(Vec<Presence>),
+    /// changes of the reloaded config
+    Reloaded(Vec<String>),
+    /// the command is done
+    Done,
+    /// the command has failed
+    Error(String),
+}
+
+/// room with the number of its messages and the users in it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomStats {
+    pub name: RoomName,
+    pub messages: u64,
+    pub users: Vec<Username>,
+}
@@ -1,154 +1,373 @@
 use crate::message::{ClientMessage, ServerMessage};
 use byteorder::{BigEndian, ByteOrder};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{buf::BufMutExt, Buf, BufMut, BytesMut};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    borrow::Cow,
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder};
 
-/// Codec for Client -> Server transport
-#[derive(Default)]
-pub struct ClientWriteCodec;
+/// first bytes of the preface by which the client chooses the format and the compression,
+/// the preface is followed by the byte of the format and the compression
+pub const PREFACE_MAGIC: [u8; 4] = *b"RSTG";
 
-impl ClientWriteCodec {
-    pub fn new() -> Self {
-        Self
-    }
-}
+/// length of the preface with the byte of the format and the compression
+pub const PREFACE_LEN: usize = PREFACE_MAGIC.len() + 1;
 
-impl Encoder for ClientWriteCodec {
-    type Item = ClientMessage;
-    type Error = bincode::Error;
+/// default size of the frame body below which frames are sent uncompressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
 
-    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let size = bincode::serialized_size(&item)? as usize;
-        log::debug!("client encode messae size: {}", size);
+/// default maximum size of the frame body
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
 
-        // reaserve for head + body
-        dst.reserve(2 + size);
-        dst.put_u16(size as u16);
+/// length of the frame head - big-endian u32 size of the body
+const HEAD_LEN: usize = 4;
 
-        unsafe {
-            let bytes = &mut *(dst.bytes_mut() as *mut [std::mem::MaybeUninit<u8>] as *mut [u8]);
-            bincode::serialize_into(bytes, &item)?;
-            dst.advance_mut(size);
-        }
+/// the highest bit of the frame head is set if the body is compressed
+const COMPRESSED_FLAG: u32 = 1 << 31;
 
-        Ok(())
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unknown format: {0}")]
+    UnknownFormat(u8),
+    #[error("unknown compression: {0}")]
+    UnknownCompression(u8),
+    #[error("compressed frame on connection without compression")]
+    UnexpectedCompression,
+    #[error("frame of {size} bytes exceeds maximum of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+}
 
-        // safe but with copy variant:
-        // let msg = bincode::serialize(&item)?;
-        // let msg_ref: &[u8] = msg.as_ref();
+/// serialization format of the frame body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Bincode,
+    /// UTF-8 JSON, see PROTOCOL.md for the schema
+    Json,
+}
 
-        // dst.reserve(msg_ref.len() + 2);
-        // dst.put_u16(msg_ref.len() as u16);
-        // dst.put(msg_ref);
+/// compression of frames, frames smaller than the threshold are sent uncompressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+/// format and compression the client chooses for the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preface {
+    pub format: Format,
+    pub compression: Compression,
+}
 
-        // Ok(())
+/// checks the size of the frame body
+fn check_size(size: usize, max: usize) -> Result<(), Error> {
+    if size > max {
+        return Err(Error::FrameTooLarge { size, max });
     }
+
+    Ok(())
 }
 
-/// Codec for Client <- Server transport
-#[derive(Default)]
-pub struct ClientReadCodec;
+/// reads the head of the frame and returns the size of the body and whether it is compressed
+/// if the whole frame is available, the head is advanced only in that case;
+/// rejects frames larger than 'max' before buffering them
+fn decode_head(src: &mut BytesMut, max: usize) -> Result<Option<(usize, bool)>, Error> {
+    // read head
+    let head = {
+        if src.len() < HEAD_LEN {
+            return Ok(None);
+        }
+        BigEndian::read_u32(src.as_ref())
+    };
+    let compressed = head & COMPRESSED_FLAG != 0;
+    let size = (head & !COMPRESSED_FLAG) as usize;
+    check_size(size, max)?;
 
-impl ClientReadCodec {
-    pub fn new() -> Self {
-        Self
+    // reserve bytes for current frame body and next frame head
+    src.reserve(size + HEAD_LEN);
+
+    if src.len() >= size + HEAD_LEN {
+        src.advance(HEAD_LEN);
+        Ok(Some((size, compressed)))
+    } else {
+        Ok(None)
     }
 }
 
-impl Decoder for ClientReadCodec {
-    type Item = ServerMessage;
-    type Error = bincode::Error;
+impl Preface {
+    /// uncompressed bincode, used for connections without preface
+    pub const DEFAULT: Self = Self {
+        format: Format::Bincode,
+        compression: Compression::None,
+    };
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // read head
-        let size = {
-            if src.len() < 2 {
-                return Ok(None);
-            }
-            BigEndian::read_u16(src.as_ref()) as usize
+    pub fn new(format: Format, compression: Compression) -> Self {
+        Self {
+            format,
+            compression,
+        }
+    }
+
+    /// returns the preface the client sends right after connecting,
+    /// the low 4 bits of the last byte are the format and the high 4 bits are the compression
+    pub fn to_bytes(self) -> [u8; PREFACE_LEN] {
+        let format = match self.format {
+            Format::Bincode => 0,
+            Format::Json => 1,
+        };
+        let compression = match self.compression {
+            Compression::None => 0,
+            Compression::Deflate => 1,
         };
-        log::debug!("client docode message size: {}", size);
-
-        // reserve bytes for current frame body and next frame head
-        src.reserve(size + 2);
-
-        // read body
-        if src.len() >= size + 2 {
-            src.advance(2);
-            let buf = src.split_to(size);
-            Ok(Some(bincode::deserialize(&buf)?))
-        } else {
-            Ok(None)
+
+        let mut preface = [0; PREFACE_LEN];
+        preface[..PREFACE_MAGIC.len()].copy_from_slice(&PREFACE_MAGIC);
+        preface[PREFACE_MAGIC.len()] = compression << 4 | format;
+        preface
+    }
+
+    /// parses the preface, returns 'Ok(None)' if the bytes are not the preface,
+    /// then the connection is bincode one without preface and the bytes start the first frame
+    pub fn parse(bytes: &[u8; PREFACE_LEN]) -> Result<Option<Self>, Error> {
+        if bytes[..PREFACE_MAGIC.len()] != PREFACE_MAGIC {
+            return Ok(None);
         }
+
+        let byte = bytes[PREFACE_MAGIC.len()];
+        let format = match byte & 0x0f {
+            0 => Format::Bincode,
+            1 => Format::Json,
+            x => return Err(Error::UnknownFormat(x)),
+        };
+        let compression = match byte >> 4 {
+            0 => Compression::None,
+            1 => Compression::Deflate,
+            x => return Err(Error::UnknownCompression(x)),
+        };
+
+        Ok(Some(Self::new(format, compression)))
+    }
+}
+
+impl Default for Preface {
+    fn default() -> Self {
+        Self::DEFAULT
     }
 }
 
+/// length-delimited codec, decodes 'In' and encodes 'Out' in the format chosen by the client
+pub struct Codec<In, Out> {
+    format: Format,
+    compression: Compression,
+    compression_threshold: usize,
+    max_frame_size: usize,
+    _marker: PhantomData<fn(Out) -> In>,
+}
+
 /// Codec for Server -> Client and Server <- Client transport
-#[derive(Default)]
-pub struct ServerCodec;
+pub type ServerCodec = Codec<ClientMessage, ServerMessage>;
+
+/// Codec for Client -> Server and Client <- Server transport
+pub type ClientCodec = Codec<ServerMessage, ClientMessage>;
 
-impl ServerCodec {
+impl<In, Out> Codec<In, Out> {
+    /// creates bincode codec
     pub fn new() -> Self {
-        Self
+        Self::with_format(Format::Bincode)
     }
-}
 
-impl Encoder for ServerCodec {
-    type Item = ServerMessage;
-    type Error = bincode::Error;
+    /// creates uncompressed codec
+    pub fn with_format(format: Format) -> Self {
+        Self::with_preface(Preface::new(format, Compression::None))
+    }
 
-    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let size = bincode::serialized_size(&item)? as usize;
-        log::debug!("server encode message size: {}", size);
+    /// creates codec with the format and the compression chosen by the preface
+    pub fn with_preface(preface: Preface) -> Self {
+        Self {
+            format: preface.format,
+            compression: preface.compression,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            _marker: PhantomData,
+        }
+    }
 
-        // reaserve for head + body
-        dst.reserve(2 + size);
-        dst.put_u16(size as u16);
+    /// creates bincode codec which rejects frames larger than 'max_frame_size' bytes
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self::new().max_frame_size(max_frame_size)
+    }
 
-        unsafe {
-            let bytes = &mut *(dst.bytes_mut() as *mut [std::mem::MaybeUninit<u8>] as *mut [u8]);
-            bincode::serialize_into(bytes, &item)?;
-            dst.advance_mut(size);
+    /// sets maximum size of frames in bytes
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// sets size of the frame body below which frames are sent uncompressed
+    pub fn compression_threshold(mut self, compression_threshold: usize) -> Self {
+        self.compression_threshold = compression_threshold;
+        self
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// compresses the body of the frame starting at 'start' if it is not smaller than
+    /// the threshold and the compressed body is smaller
+    fn compress(&self, dst: &mut BytesMut, start: usize) -> io::Result<()> {
+        let body = &dst[start + HEAD_LEN..];
+        if self.compression == Compression::None || body.len() < self.compression_threshold {
+            return Ok(());
         }
 
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(body)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() >= body.len() {
+            return Ok(());
+        }
+        log::debug!(
+            "compress message size: {} -> {}",
+            body.len(),
+            compressed.len()
+        );
+
+        dst.truncate(start + HEAD_LEN);
+        dst.extend_from_slice(&compressed);
+        BigEndian::write_u32(&mut dst[start..], compressed.len() as u32 | COMPRESSED_FLAG);
         Ok(())
+    }
+
+    /// decompresses the body, which must not exceed maximum size of frames after decompression
+    fn decompress(&self, body: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.compression == Compression::None {
+            return Err(Error::UnexpectedCompression);
+        }
+
+        // reads one byte more than allowed to find out that the body is too large
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(body)
+            .take(self.max_frame_size as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+        check_size(decompressed.len(), self.max_frame_size)?;
+
+        Ok(decompressed)
+    }
+}
+
+impl<In, Out: Serialize> Codec<In, Out> {
+    /// serializes the message without the head for transports framing messages themselves,
+    /// e.g. WebSocket; the body is never compressed
+    pub fn encode_body(&self, item: Out) -> Result<Vec<u8>, Error> {
+        let body = match self.format {
+            Format::Bincode => bincode::serialize(&item)?,
+            Format::Json => serde_json::to_vec(&item)?,
+        };
+        check_size(body.len(), self.max_frame_size)?;
+
+        Ok(body)
+    }
+}
+
+impl<In: DeserializeOwned, Out> Codec<In, Out> {
+    /// deserializes the message without the head, the counterpart of 'encode_body'
+    pub fn decode_body(&self, body: &[u8]) -> Result<In, Error> {
+        check_size(body.len(), self.max_frame_size)?;
+
+        let item = match self.format {
+            Format::Bincode => bincode::deserialize(body)?,
+            Format::Json => serde_json::from_slice(body)?,
+        };
+        Ok(item)
+    }
+}
+
+impl<In, Out> Default for Codec<In, Out> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<In, Out: Serialize> Encoder for Codec<In, Out> {
+    type Item = Out;
+    type Error = Error;
 
-        // safe but with copy variant:
-        // let msg = bincode::serialize(&item)?;
-        // let msg_ref: &[u8] = msg.as_ref();
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+
+        match self.format {
+            Format::Bincode => {
+                let size = bincode::serialized_size(&item)? as usize;
+                log::debug!("encode message size: {}", size);
+                check_size(size, self.max_frame_size)?;
 
-        // dst.reserve(msg_ref.len() + 2);
-        // dst.put_u16(msg_ref.len() as u16);
-        // dst.put(msg_ref);
+                // reaserve for head + body
+                dst.reserve(HEAD_LEN + size);
+                dst.put_u32(size as u32);
+                bincode::serialize_into(dst.writer(), &item)?;
+            }
+            Format::Json => {
+                // the size is unknown until serialization, so the head is written after the body
+                dst.put_u32(0);
+                let res = serde_json::to_writer(dst.writer(), &item)
+                    .map_err(Error::from)
+                    .and_then(|_| {
+                        let size = dst.len() - start - HEAD_LEN;
+                        log::debug!("encode message size: {}", size);
+                        check_size(size, self.max_frame_size).map(|_| size)
+                    });
+
+                match res {
+                    Ok(size) => BigEndian::write_u32(&mut dst[start..], size as u32),
+                    Err(e) => {
+                        dst.truncate(start);
+                        return Err(e);
+                    }
+                }
+            }
+        }
 
-        // Ok(())
+        self.compress(dst, start)?;
+        Ok(())
     }
 }
 
-impl Decoder for ServerCodec {
-    type Item = ClientMessage;
-    type Error = bincode::Error;
+impl<In: DeserializeOwned, Out> Decoder for Codec<In, Out> {
+    type Item = In;
+    type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        // read head
-        let size = {
-            if src.len() < 2 {
-                return Ok(None);
+        match decode_head(src, self.max_frame_size)? {
+            Some((size, compressed)) => {
+                log::debug!("decode message size: {}", size);
+
+                // read body
+                let buf = src.split_to(size);
+                let body = if compressed {
+                    Cow::Owned(self.decompress(&buf)?)
+                } else {
+                    Cow::Borrowed(&buf[..])
+                };
+
+                self.decode_body(&body).map(Some)
             }
-            BigEndian::read_u16(src.as_ref()) as usize
-        };
-        log::debug!("server decode message size: {}", size);
-
-        // reserve bytes for current frame body and next frame head
-        src.reserve(size + 2);
-
-        // read body
-        if src.len() >= size + 2 {
-            src.advance(2);
-            let buf = src.split_to(size);
-            Ok(Some(bincode::deserialize(&buf)?))
-        } else {
-            Ok(None)
+            None => Ok(None),
         }
     }
 }
@@ -0,0 +1,210 @@
+// end-to-end encryption of direct messages, the server only routes and stores ciphertexts:
+//     1. each account publishes bundle of public identity key and prekey through the server
+//     2. the sender fetches the bundle of the recipient and generates ephemeral key
+//     3. the message key is derived from three Diffie-Hellman exchanges:
+//        (sender identity, recipient prekey), (ephemeral, recipient identity),
+//        (ephemeral, recipient prekey), so only the recipient can derive it and only
+//        the owner of the sender identity key could produce it
+//     4. the message is encrypted with ChaCha20-Poly1305, usernames are authenticated with it
+// the server could substitute bundles, so users should compare fingerprints out of band
+use crate::account::Username;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key,
+};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use thiserror::Error;
+use x25519_dalek::{SharedSecret, StaticSecret};
+
+pub type PublicKey = [u8; 32];
+pub type SecretKey = [u8; 32];
+pub type Nonce = [u8; 12];
+
+/// maximum length of ciphertext the server accepts
+pub const MAX_CIPHERTEXT_LEN: usize = 4096;
+
+const INFO: &[u8] = b"rustenger e2e v1";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid public key")]
+    InvalidKey,
+    #[error("message is too long")]
+    TooLong,
+    #[error("failed to decrypt message")]
+    Decrypt,
+}
+
+/// public keys an account publishes so that others can send it encrypted messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBundle {
+    pub identity: PublicKey,
+    pub prekey: PublicKey,
+}
+
+impl KeyBundle {
+    /// hex SHA-256 of the identity key for verification out of band
+    pub fn fingerprint(&self) -> String {
+        let mut buffer = String::with_capacity(64);
+        for byte in Sha256::digest(self.identity) {
+            write!(buffer, "{:02x}", byte).unwrap();
+        }
+        buffer
+    }
+}
+
+/// ciphertext with ephemeral key of the sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedMessage {
+    pub ephemeral: PublicKey,
+    pub nonce: Nonce,
+    pub ciphertext: Vec<u8>,
+}
+
+/// private keys of an account, never leave the client
+pub struct KeyPair {
+    identity: StaticSecret,
+    prekey: StaticSecret,
+}
+
+impl KeyPair {
+    /// generates random keys
+    pub fn generate() -> Self {
+        let identity = StaticSecret::random_from_rng(OsRng);
+        let prekey = StaticSecret::random_from_rng(OsRng);
+        Self { identity, prekey }
+    }
+
+    /// restores keys saved by 'to_bytes'
+    pub fn from_bytes(identity: SecretKey, prekey: SecretKey) -> Self {
+        let identity = StaticSecret::from(identity);
+        let prekey = StaticSecret::from(prekey);
+        Self { identity, prekey }
+    }
+
+    /// returns identity and prekey secrets to be saved
+    pub fn to_bytes(&self) -> (SecretKey, SecretKey) {
+        (self.identity.to_bytes(), self.prekey.to_bytes())
+    }
+
+    /// returns public keys to be published
+    pub fn bundle(&self) -> KeyBundle {
+        KeyBundle {
+            identity: x25519_dalek::PublicKey::from(&self.identity).to_bytes(),
+            prekey: x25519_dalek::PublicKey::from(&self.prekey).to_bytes(),
+        }
+    }
+
+    /// encrypts 'plaintext' from 'sender' (owner of the keys) to 'recipient' with 'bundle'
+    pub fn encrypt(
+        &self,
+        sender: Username,
+        recipient: Username,
+        bundle: &KeyBundle,
+        plaintext: &[u8],
+    ) -> Result<EncryptedMessage> {
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let recipient_identity = x25519_dalek::PublicKey::from(bundle.identity);
+        let recipient_prekey = x25519_dalek::PublicKey::from(bundle.prekey);
+
+        let key = derive_key(
+            [
+                self.identity.diffie_hellman(&recipient_prekey),
+                ephemeral.diffie_hellman(&recipient_identity),
+                ephemeral.diffie_hellman(&recipient_prekey),
+            ],
+            sender,
+            recipient,
+        )?;
+
+        let nonce = rand::random::<Nonce>();
+        let aad = associated_data(sender, recipient);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &aad,
+        };
+        let ciphertext = cipher(&key)
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| Error::TooLong)?;
+        if ciphertext.len() > MAX_CIPHERTEXT_LEN {
+            return Err(Error::TooLong);
+        }
+
+        Ok(EncryptedMessage {
+            ephemeral: x25519_dalek::PublicKey::from(&ephemeral).to_bytes(),
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// decrypts message from 'sender' with identity key from 'bundle' to 'recipient' (owner of the keys)
+    pub fn decrypt(
+        &self,
+        sender: Username,
+        recipient: Username,
+        bundle: &KeyBundle,
+        msg: &EncryptedMessage,
+    ) -> Result<Vec<u8>> {
+        let sender_identity = x25519_dalek::PublicKey::from(bundle.identity);
+        let ephemeral = x25519_dalek::PublicKey::from(msg.ephemeral);
+
+        let key = derive_key(
+            [
+                self.prekey.diffie_hellman(&sender_identity),
+                self.identity.diffie_hellman(&ephemeral),
+                self.prekey.diffie_hellman(&ephemeral),
+            ],
+            sender,
+            recipient,
+        )?;
+
+        let aad = associated_data(sender, recipient);
+        let payload = Payload {
+            msg: &msg.ciphertext,
+            aad: &aad,
+        };
+        cipher(&key)
+            .decrypt(&msg.nonce.into(), payload)
+            .map_err(|_| Error::Decrypt)
+    }
+}
+
+/// derives message key from shared secrets, rejects low order public keys
+fn derive_key(secrets: [SharedSecret; 3], sender: Username, recipient: Username) -> Result<Key> {
+    let mut ikm = Vec::with_capacity(32 * secrets.len());
+    for secret in &secrets {
+        if !secret.was_contributory() {
+            return Err(Error::InvalidKey);
+        }
+        ikm.extend_from_slice(secret.as_bytes());
+    }
+
+    let mut info = INFO.to_vec();
+    info.extend_from_slice(&associated_data(sender, recipient));
+
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(None, &ikm)
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid length for HKDF-SHA256");
+    Ok(key)
+}
+
+/// length-prefixed usernames, so that different pairs never give the same bytes
+fn associated_data(sender: Username, recipient: Username) -> Vec<u8> {
+    let mut data = Vec::with_capacity(2 + sender.len() + recipient.len());
+    for username in &[sender, recipient] {
+        data.push(username.len() as u8);
+        data.extend_from_slice(username.as_bytes());
+    }
+    data
+}
+
+fn cipher(key: &Key) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(key)
+}
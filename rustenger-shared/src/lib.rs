@@ -1,7 +1,11 @@
 use arrayvec::ArrayString;
 
 pub mod account;
+pub mod admin;
 pub mod codec;
+pub mod e2e;
 pub mod message;
+pub mod scram;
+pub mod tls;
 
 pub type RoomName = ArrayString<[u8; 32]>;
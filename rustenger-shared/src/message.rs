@@ -1,5 +1,7 @@
 use super::{
-    account::{Account, Color, Password, Username},
+    account::{Account, Color, Password, Presence, Status, StatusMessage, Username},
+    e2e::{EncryptedMessage, KeyBundle},
+    scram::{Challenge, Nonce, Proof, Signature},
     RoomName,
 };
 use arrayvec::ArrayString;
@@ -10,17 +12,33 @@ use thiserror::Error;
 /// contains only text of message
 pub type UserMessage = ArrayString<[u8; 1024]>;
 
+/// identifier of a message, unique within a room
+pub type MessageId = u64;
+
+/// reaction to a message, usually an emoji
+pub type Reaction = ArrayString<[u8; 16]>;
+
 /// message from client
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
-    UserMessage(UserMessage),
+    /// text of message and id of the message it replies to,
+    /// the text is boxed, so other messages do not take its size
+    UserMessage(Box<UserMessage>, Option<MessageId>),
     Command(Command),
+    /// user started or stopped typing
+    Typing(bool),
+    /// end-to-end encrypted message to the account, the server can not read it
+    Direct(Username, EncryptedMessage),
+    /// keepalive, the server answers with 'Pong'
+    Ping,
+    /// acknowledges that direct message 'id' is delivered to the client
+    Ack(MessageId),
 }
 
 impl ClientMessage {
-    pub fn user_message(self) -> Option<UserMessage> {
+    pub fn user_message(self) -> Option<(UserMessage, Option<MessageId>)> {
         match self {
-            Self::UserMessage(x) => Some(x),
+            Self::UserMessage(x, p) => Some((*x, p)),
             _ => None,
         }
     }
@@ -31,6 +49,13 @@ impl ClientMessage {
             _ => None,
         }
     }
+
+    pub fn direct(self) -> Option<(Username, EncryptedMessage)> {
+        match self {
+            Self::Direct(un, x) => Some((un, x)),
+            _ => None,
+        }
+    }
 }
 
 /// command to server
@@ -38,11 +63,37 @@ impl ClientMessage {
 pub enum Command {
     LogIn(Username, Password),
     SignUp(Username, Password),
+    /// starts challenge-response log in with client nonce
+    ScramStart(Username, Nonce),
+    /// proof for the challenge received in reply to 'ScramStart'
+    ScramProof(Proof),
     CreateRoom(RoomName),
     SelectRoom(RoomName),
     ExitRoom,
     RoomsList,
     SelectColor(Color),
+    /// requests the whole thread containing the message
+    Thread(MessageId),
+    React(MessageId, Reaction),
+    Unreact(MessageId, Reaction),
+    /// requests and clears the mention inbox
+    Mentions,
+    SetStatus(Status, StatusMessage),
+    WhoIs(Username),
+    /// requests the page of online accounts, starting from 0, with the room each one is in;
+    /// there are no private rooms, every room is public, so the room is always listed
+    WhoIsOnline(u32),
+    /// publishes public keys of the account, replacing previous ones
+    PublishKeys(KeyBundle),
+    FetchKeys(Username),
+    /// requests and clears the inbox of direct messages received while not in a room
+    Directs,
+    /// marks messages of the room up to and including 'id' as read
+    MarkRead(RoomName, MessageId),
+    /// marks direct message 'id' as read, the sender receives 'Receipt'
+    MarkDirectRead(MessageId),
+    /// requests and clears receipts of sent direct messages received while not in a room
+    Receipts,
     DeleteAccount,
     Exit,
 }
@@ -50,14 +101,30 @@ pub enum Command {
 /// message form server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
-    AccountMessage(AccountMessage),
+    /// boxed, so other messages do not take the size of its text
+    AccountMessage(Box<AccountMessage>),
+    Direct(DirectMessage),
     Response(Response),
+    Event(Event),
+    /// answer to 'Ping'
+    Pong,
+    /// status of sent direct message has changed
+    Receipt(Receipt),
+    /// message of the day, sent after sign in if the server has it
+    Motd(String),
 }
 
 impl ServerMessage {
     pub fn account_message(self) -> Option<AccountMessage> {
         match self {
-            Self::AccountMessage(x) => Some(x),
+            Self::AccountMessage(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn direct(self) -> Option<DirectMessage> {
+        match self {
+            Self::Direct(x) => Some(x),
             _ => None,
         }
     }
@@ -68,22 +135,146 @@ impl ServerMessage {
             _ => None,
         }
     }
+
+    pub fn event(self) -> Option<Event> {
+        match self {
+            Self::Event(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn receipt(self) -> Option<Receipt> {
+        match self {
+            Self::Receipt(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn motd(self) -> Option<String> {
+        match self {
+            Self::Motd(x) => Some(x),
+            _ => None,
+        }
+    }
 }
 
 /// UserMessage with adresser and time
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountMessage {
+    pub id: MessageId,
+    /// id of the message this one replies to
+    pub parent: Option<MessageId>,
     pub text: UserMessage,
     pub adresser: Account,
     pub utc: DateTime<Utc>,
+    /// reactions with the number of accounts reacted so
+    pub reactions: Vec<(Reaction, u32)>,
+    /// whether the recipient is mentioned in the message
+    pub mentioned: bool,
+}
+
+/// end-to-end encrypted message with adresser and time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectMessage {
+    /// identifier unique within the server
+    pub id: MessageId,
+    pub adresser: Account,
+    pub recipient: Username,
+    pub utc: DateTime<Utc>,
+    pub payload: EncryptedMessage,
+}
+
+/// status of direct message, the sender is notified about each change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DirectStatus {
+    /// the recipient has received the message
+    Delivered,
+    /// the recipient has read the message
+    Read,
+}
+
+/// 'recipient' has received or read direct message 'id'
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Receipt {
+    pub id: MessageId,
+    pub recipient: Username,
+    pub status: DirectStatus,
+}
+
+/// message that mentions the account and the room where it was sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mention {
+    pub room: RoomName,
+    pub message: AccountMessage,
+}
+
+/// notification about a change in the room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    Reaction(ReactionEvent),
+    /// account started or stopped typing
+    Typing(Account, bool),
+    /// account left the room or was disconnected
+    Leave(Account),
+    /// the server is shutting down, the connection is closed after it
+    Shutdown,
+    /// announcement of the server operator
+    Announcement(String),
+    /// the room is deleted by the server operator, the account is moved out of it
+    RoomDeleted(RoomName),
+}
+
+/// 'adresser' added or removed 'reaction' to message 'id'
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ReactionEvent {
+    pub id: MessageId,
+    pub reaction: Reaction,
+    pub adresser: Account,
+    pub added: bool,
+    /// number of accounts reacted so after the change
+    pub count: u32,
 }
 
 /// response to client Request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
-    RoomsList(Vec<RoomName>),
+    RoomsList(Vec<RoomInfo>),
     RoomAccountsList(Vec<Account>),
+    /// messages of a thread, each reply follows its parent
+    Thread(Vec<AccountMessage>),
+    Mentions(Vec<Mention>),
+    /// presence of the account, 'None' if the server has never seen it
+    WhoIs(Option<Presence>),
+    OnlineList(OnlinePage),
+    /// public keys of the account, 'None' if it has not published them
+    Keys(Username, Option<KeyBundle>),
+    Directs(Vec<DirectMessage>),
+    Receipts(Vec<Receipt>),
+    ScramChallenge(Challenge),
+    /// proves the server knows the account credentials, followed by 'SignInResult'
+    ScramSignature(Signature),
     SignInResult(Result<(), SignInError>),
+    /// the message the command refers to does not exist in the room,
+    /// e.g. the parent of a reply
+    UnknownMessage(MessageId),
+    /// the account the command refers to does not exist, e.g. the recipient of a direct message
+    UnknownUser(Username),
+}
+
+/// room with the number of messages the account has not read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub name: RoomName,
+    pub unread: u64,
+}
+
+/// page of online accounts sorted by username
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnlinePage {
+    pub page: u32,
+    /// total number of pages
+    pub pages: u32,
+    pub accounts: Vec<Presence>,
 }
 
 #[derive(Error, Clone, Debug, Serialize, Deserialize)]
@@ -92,4 +283,6 @@ pub enum SignInError {
     InvalidUserNamePassword,
     #[error("this username already used")]
     UserNameAlreadyUsed,
+    #[error("this account is banned")]
+    Banned,
 }
@@ -0,0 +1,209 @@
+// SCRAM-SHA-256 like challenge-response authentication,
+// the password never crosses the wire and the server stores only derived keys:
+//     1. client -> server: username and client nonce
+//     2. server -> client: salt, iterations and server nonce
+//     3. client -> server: proof of knowledge of the password
+//     4. server -> client: signature proving the server knows the derived keys
+use crate::account::{Password, Username};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub type Nonce = [u8; 32];
+pub type Salt = [u8; 16];
+pub type Proof = [u8; 32];
+pub type Signature = [u8; 32];
+/// secret of the server which challenges for unknown accounts are made up with
+pub type Secret = [u8; 32];
+
+type Key = [u8; 32];
+
+/// number of PBKDF2 iterations for new credentials,
+/// clients refuse challenges with less iterations
+pub const ITERATIONS: u32 = 4096;
+
+/// maximum number of PBKDF2 iterations clients accept,
+/// so a malicious server can not make them derive keys for long
+pub const MAX_ITERATIONS: u32 = ITERATIONS * 256;
+
+/// generates random nonce
+pub fn nonce() -> Nonce {
+    rand::random()
+}
+
+/// generates random secret, the server generates it once on start
+pub fn secret() -> Secret {
+    rand::random()
+}
+
+/// makes up challenge for the account which does not exist, the salt is derived
+/// from 'secret' and the username, so it is the same on each attempt as the salt
+/// of an existing account, and no key derivation is run
+pub fn unknown_challenge(secret: &Secret, username: &Username) -> Challenge {
+    let key = hmac(secret, username.as_bytes());
+    let mut salt = Salt::default();
+    let len = salt.len();
+    salt.copy_from_slice(&key[..len]);
+
+    Challenge {
+        salt,
+        iterations: ITERATIONS,
+        server_nonce: nonce(),
+    }
+}
+
+/// parameters the server challenges the client with
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Challenge {
+    pub salt: Salt,
+    pub iterations: u32,
+    pub server_nonce: Nonce,
+}
+
+/// keys derived from the password which the server stores instead of it
+#[derive(Debug, Clone, Copy)]
+pub struct Credentials {
+    salt: Salt,
+    iterations: u32,
+    stored_key: Key,
+    server_key: Key,
+}
+
+impl Credentials {
+    /// derives credentials from the password with random salt
+    pub fn new(password: &Password) -> Self {
+        Self::with_salt(password, rand::random(), ITERATIONS)
+    }
+
+    /// derives credentials from the password
+    pub fn with_salt(password: &Password, salt: Salt, iterations: u32) -> Self {
+        let salted = salted_password(password, &salt, iterations);
+        let stored_key = Sha256::digest(hmac(&salted, b"Client Key")).into();
+        let server_key = hmac(&salted, b"Server Key");
+
+        Self {
+            salt,
+            iterations,
+            stored_key,
+            server_key,
+        }
+    }
+
+    /// checks the plain password
+    pub fn verify_password(&self, password: &Password) -> bool {
+        let other = Self::with_salt(password, self.salt, self.iterations);
+        bool::from(self.stored_key.ct_eq(&other.stored_key))
+    }
+
+    /// creates challenge with random server nonce
+    pub fn challenge(&self) -> Challenge {
+        Challenge {
+            salt: self.salt,
+            iterations: self.iterations,
+            server_nonce: nonce(),
+        }
+    }
+
+    /// checks the client proof and returns server signature if it is valid
+    pub fn verify_proof(
+        &self,
+        username: &Username,
+        client_nonce: &Nonce,
+        challenge: &Challenge,
+        proof: &Proof,
+    ) -> Option<Signature> {
+        let auth_message = auth_message(username, client_nonce, challenge);
+        let client_signature = hmac(&self.stored_key, &auth_message);
+        let client_key = xor(proof, &client_signature);
+
+        let stored_key = Sha256::digest(client_key);
+        if bool::from(self.stored_key.ct_eq(stored_key.as_slice())) {
+            Some(hmac(&self.server_key, &auth_message))
+        } else {
+            None
+        }
+    }
+}
+
+/// client side of the exchange
+#[derive(Debug, Clone, Copy)]
+pub struct ClientExchange {
+    username: Username,
+    password: Password,
+    nonce: Nonce,
+}
+
+impl ClientExchange {
+    /// starts exchange with random client nonce
+    pub fn new(username: Username, password: Password) -> Self {
+        let nonce = nonce();
+        Self {
+            username,
+            password,
+            nonce,
+        }
+    }
+
+    pub fn username(&self) -> Username {
+        self.username
+    }
+
+    pub fn nonce(&self) -> Nonce {
+        self.nonce
+    }
+
+    /// computes proof for the challenge and the server signature expected in reply,
+    /// returns 'None' if the challenge is weaker than 'ITERATIONS' or harder than 'MAX_ITERATIONS'
+    pub fn proof(&self, challenge: &Challenge) -> Option<(Proof, Signature)> {
+        if !(ITERATIONS..=MAX_ITERATIONS).contains(&challenge.iterations) {
+            return None;
+        }
+
+        let salted = salted_password(&self.password, &challenge.salt, challenge.iterations);
+        let client_key = hmac(&salted, b"Client Key");
+        let stored_key: Key = Sha256::digest(client_key).into();
+        let server_key = hmac(&salted, b"Server Key");
+
+        let auth_message = auth_message(&self.username, &self.nonce, challenge);
+        let proof = xor(&client_key, &hmac(&stored_key, &auth_message));
+        let signature = hmac(&server_key, &auth_message);
+
+        Some((proof, signature))
+    }
+
+    /// checks the server signature in constant time
+    pub fn verify_signature(expected: &Signature, signature: &Signature) -> bool {
+        bool::from(expected.ct_eq(signature))
+    }
+}
+
+fn salted_password(password: &Password, salt: &Salt, iterations: u32) -> Key {
+    let mut salted = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted);
+    salted
+}
+
+fn auth_message(username: &Username, client_nonce: &Nonce, challenge: &Challenge) -> Vec<u8> {
+    let mut message = Vec::with_capacity(username.len() + 32 + 32 + 16 + 4);
+    message.extend_from_slice(username.as_bytes());
+    message.extend_from_slice(client_nonce);
+    message.extend_from_slice(&challenge.server_nonce);
+    message.extend_from_slice(&challenge.salt);
+    message.extend_from_slice(&challenge.iterations.to_be_bytes());
+    message
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Key {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn xor(a: &Key, b: &Key) -> Key {
+    let mut res = Key::default();
+    for (r, (a, b)) in res.iter_mut().zip(a.iter().zip(b)) {
+        *r = a ^ b;
+    }
+    res
+}
@@ -0,0 +1,177 @@
+use rustls::{
+    internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+    AllowAnyAnonymousOrAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+    RootCertStore, ServerConfig, TLSError,
+};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+    sync::Arc,
+};
+use thiserror::Error;
+use x509_parser::{pem::Pem, prelude::FromDer, revocation_list::CertificateRevocationList};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("no valid certificates in '{0}'")]
+    InvalidCertificate(String),
+    #[error("no valid private key in '{0}'")]
+    InvalidPrivateKey(String),
+    #[error("no valid certificate revocation lists in '{0}'")]
+    InvalidRevocationList(String),
+    #[error("tls error: {0}")]
+    Tls(#[from] TLSError),
+}
+
+/// creates server config with the certificate chain and the private key from PEM files
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let config = ServerConfig::new(NoClientAuth::new());
+    with_single_cert(config, cert, key)
+}
+
+/// creates server config like 'server_config' which also verifies client certificates
+/// against CA certificates from PEM file 'client_ca'; clients without certificates are allowed
+pub fn server_config_with_client_auth(
+    cert: &Path,
+    key: &Path,
+    client_ca: &Path,
+) -> Result<Arc<ServerConfig>> {
+    let roots = load_root_store(client_ca)?;
+    let config = ServerConfig::new(AllowAnyAnonymousOrAuthenticatedClient::new(roots));
+    with_single_cert(config, cert, key)
+}
+
+/// sets the certificate chain and the private key from PEM files to server config
+fn with_single_cert(
+    mut config: ServerConfig,
+    cert: &Path,
+    key: &Path,
+) -> Result<Arc<ServerConfig>> {
+    config.set_single_cert(load_certs(cert)?, load_private_key(key)?)?;
+    Ok(Arc::new(config))
+}
+
+/// creates client config which trusts only certificates from PEM file 'ca'
+pub fn client_config(ca: &Path) -> Result<Arc<ClientConfig>> {
+    let mut config = ClientConfig::new();
+    config.root_store = load_root_store(ca)?;
+
+    Ok(Arc::new(config))
+}
+
+/// creates client config like 'client_config' which authenticates the client
+/// with the certificate chain and the private key from PEM files
+pub fn client_config_with_cert(ca: &Path, cert: &Path, key: &Path) -> Result<Arc<ClientConfig>> {
+    let mut config = ClientConfig::new();
+    config.root_store = load_root_store(ca)?;
+    config.set_single_client_cert(load_certs(cert)?, load_private_key(key)?)?;
+
+    Ok(Arc::new(config))
+}
+
+/// reads all trusted CA certificates from PEM file
+fn load_root_store(path: &Path) -> Result<RootCertStore> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots
+        .add_pem_file(&mut reader)
+        .map_err(|_| invalid_certificate(path))?;
+    if added == 0 {
+        return Err(invalid_certificate(path));
+    }
+
+    Ok(roots)
+}
+
+/// reads all certificates from PEM file
+pub fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = certs(&mut reader).map_err(|_| invalid_certificate(path))?;
+    if certs.is_empty() {
+        return Err(invalid_certificate(path));
+    }
+
+    Ok(certs)
+}
+
+/// reads the first PKCS8 or RSA private key from PEM file
+pub fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let invalid = || Error::InvalidPrivateKey(path.display().to_string());
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut keys = pkcs8_private_keys(&mut reader).map_err(|_| invalid())?;
+    if keys.is_empty() {
+        let mut reader = BufReader::new(File::open(path)?);
+        keys = rsa_private_keys(&mut reader).map_err(|_| invalid())?;
+    }
+
+    keys.into_iter().next().ok_or_else(invalid)
+}
+
+/// returns the common name of the certificate subject
+pub fn common_name(cert: &Certificate) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    let name = cert.subject().iter_common_name().next()?;
+    name.as_str().ok().map(String::from)
+}
+
+/// certificates revoked by certificate revocation lists;
+/// the lists are trusted local files, so their signatures are not verified
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    /// issuer name and serial number of each revoked certificate
+    revoked: HashSet<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RevocationList {
+    /// reads all certificate revocation lists from PEM or DER file
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || Error::InvalidRevocationList(path.display().to_string());
+
+        let data = fs::read(path)?;
+        let ders = if data.starts_with(b"-----BEGIN") {
+            Pem::iter_from_buffer(&data)
+                .map(|pem| pem.map(|pem| pem.contents).map_err(|_| invalid()))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            vec![data]
+        };
+        if ders.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut revoked = HashSet::new();
+        for der in ders {
+            let (_, crl) = CertificateRevocationList::from_der(&der).map_err(|_| invalid())?;
+            let issuer = crl.issuer().as_raw();
+            revoked.extend(
+                crl.iter_revoked_certificates()
+                    .map(|c| (issuer.to_vec(), c.raw_serial().to_vec())),
+            );
+        }
+
+        Ok(Self { revoked })
+    }
+
+    /// checks whether the certificate is revoked, invalid certificates are treated as revoked
+    pub fn is_revoked(&self, cert: &Certificate) -> bool {
+        match x509_parser::parse_x509_certificate(&cert.0) {
+            Ok((_, cert)) => {
+                let key = (cert.issuer().as_raw().to_vec(), cert.raw_serial().to_vec());
+                self.revoked.contains(&key)
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+fn invalid_certificate(path: &Path) -> Error {
+    Error::InvalidCertificate(path.display().to_string())
+}
@@ -0,0 +1,49 @@
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use rustenger_shared::{
+    account::Username,
+    admin::{AdminClientCodec, AdminRequest, AdminResponse, AdminServerCodec, RoomStats},
+    codec::Format,
+    RoomName,
+};
+use tokio::net::UnixStream;
+use tokio_util::codec::{Encoder, Framed};
+
+#[tokio::test]
+async fn operator_and_server_exchange_messages() {
+    let (operator, server) = UnixStream::pair().unwrap();
+    let mut operator = Framed::new(operator, AdminClientCodec::with_format(Format::Json));
+    let mut server = Framed::new(server, AdminServerCodec::with_format(Format::Json));
+
+    operator.send(AdminRequest::Rooms).await.unwrap();
+    match server.next().await.unwrap().unwrap() {
+        AdminRequest::Rooms => (),
+        req => panic!("unexpected request: {:?}", req),
+    }
+
+    let stats = RoomStats {
+        name: RoomName::from("general").unwrap(),
+        messages: 42,
+        users: vec![Username::from("alice").unwrap()],
+    };
+    server
+        .send(AdminResponse::Rooms(vec![stats.clone()]))
+        .await
+        .unwrap();
+    match operator.next().await.unwrap().unwrap() {
+        AdminResponse::Rooms(rooms) => assert_eq!(rooms, vec![stats]),
+        resp => panic!("unexpected response: {:?}", resp),
+    }
+}
+
+#[test]
+fn request_is_plain_json() {
+    let mut codec = AdminClientCodec::with_format(Format::Json);
+    let mut buf = BytesMut::new();
+    let username = Username::from("mallory").unwrap();
+    codec.encode(AdminRequest::Ban(username), &mut buf).unwrap();
+
+    let body = br#"{"Ban":"mallory"}"#;
+    assert_eq!(&buf[..4], &(body.len() as u32).to_be_bytes());
+    assert_eq!(&buf[4..], &body[..]);
+}
@@ -0,0 +1,102 @@
+use futures::{SinkExt, StreamExt};
+use rustenger_shared::{
+    account::Username,
+    codec::{ClientCodec, Format, ServerCodec},
+    message::{
+        ClientMessage, Command, Response, RoomInfo, ServerMessage, SignInError, UserMessage,
+    },
+    RoomName,
+};
+use tokio::net::UnixStream;
+use tokio_util::codec::Framed;
+
+type ClientFramed = Framed<UnixStream, ClientCodec>;
+type ServerFramed = Framed<UnixStream, ServerCodec>;
+
+/// client and server connected through in-memory duplex
+fn connect(format: Format) -> (ClientFramed, ServerFramed) {
+    let (client, server) = UnixStream::pair().unwrap();
+    let client = Framed::new(client, ClientCodec::with_format(format));
+    let server = Framed::new(server, ServerCodec::with_format(format));
+    (client, server)
+}
+
+#[tokio::test]
+async fn client_and_server_exchange_messages() {
+    for &format in &[Format::Bincode, Format::Json] {
+        let (mut client, mut server) = connect(format);
+
+        let username = Username::from("alice").unwrap();
+        let password = "secret".parse().unwrap();
+        client
+            .send(ClientMessage::Command(Command::SignUp(username, password)))
+            .await
+            .unwrap();
+        match server.next().await.unwrap().unwrap() {
+            ClientMessage::Command(Command::SignUp(un, pw)) => {
+                assert_eq!(un, username);
+                assert_eq!(pw, password);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        let response = Response::SignInResult(Err(SignInError::UserNameAlreadyUsed));
+        server
+            .send(ServerMessage::Response(response))
+            .await
+            .unwrap();
+        match client.next().await.unwrap().unwrap() {
+            ServerMessage::Response(Response::SignInResult(Err(
+                SignInError::UserNameAlreadyUsed,
+            ))) => (),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+}
+
+#[tokio::test]
+async fn several_messages_in_one_read() {
+    let (mut client, mut server) = connect(Format::Bincode);
+
+    let texts = ["first", "second", "third"];
+    for text in &texts {
+        let msg = ClientMessage::UserMessage(Box::new(UserMessage::from(text).unwrap()), None);
+        client.send(msg).await.unwrap();
+    }
+
+    for text in &texts {
+        match server.next().await.unwrap().unwrap() {
+            ClientMessage::UserMessage(msg, None) => assert_eq!(msg.as_str(), *text),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+}
+
+#[tokio::test]
+async fn closed_connection_ends_stream() {
+    let (client, mut server) = connect(Format::Bincode);
+    drop(client);
+
+    assert!(server.next().await.is_none());
+}
+
+#[tokio::test]
+async fn large_response_is_received() {
+    let (mut client, mut server) = connect(Format::Json);
+
+    let rooms = (0..4096)
+        .map(|i| RoomInfo {
+            name: RoomName::from(&format!("room-{:026}", i)).unwrap(),
+            unread: i,
+        })
+        .collect::<Vec<_>>();
+    let response = Response::RoomsList(rooms.clone());
+    let send = server.send(ServerMessage::Response(response));
+    let (sent, received) = futures::join!(send, client.next());
+    sent.unwrap();
+
+    match received.unwrap().unwrap() {
+        ServerMessage::Response(Response::RoomsList(received)) => assert_eq!(received, rooms),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+}
@@ -0,0 +1,269 @@
+use bytes::BytesMut;
+use rustenger_shared::{
+    account::Username,
+    codec::{self, ClientCodec, Compression, Format, Preface, ServerCodec, PREFACE_LEN},
+    message::{ClientMessage, Command, Response, RoomInfo, ServerMessage},
+    RoomName,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+#[test]
+fn preface_round_trip() {
+    for &format in &[Format::Bincode, Format::Json] {
+        for &compression in &[Compression::None, Compression::Deflate] {
+            let preface = Preface::new(format, compression);
+            assert_eq!(Preface::parse(&preface.to_bytes()).unwrap(), Some(preface));
+        }
+    }
+}
+
+#[test]
+fn connection_without_preface_is_bincode() {
+    let bytes = [0, 0, 0, 8, 1, 0, 0, 0];
+    let mut preface = [0; PREFACE_LEN];
+    preface.copy_from_slice(&bytes[..PREFACE_LEN]);
+
+    assert_eq!(Preface::parse(&preface).unwrap(), None);
+}
+
+#[test]
+fn unknown_format_is_rejected() {
+    let mut preface = Preface::new(Format::Json, Compression::None).to_bytes();
+    preface[PREFACE_LEN - 1] = 0x0a;
+
+    assert!(matches!(
+        Preface::parse(&preface),
+        Err(codec::Error::UnknownFormat(0x0a))
+    ));
+}
+
+#[test]
+fn unknown_compression_is_rejected() {
+    let mut preface = Preface::new(Format::Json, Compression::None).to_bytes();
+    preface[PREFACE_LEN - 1] = 0x71;
+
+    assert!(matches!(
+        Preface::parse(&preface),
+        Err(codec::Error::UnknownCompression(7))
+    ));
+}
+
+#[test]
+fn json_round_trip() {
+    let mut client = ClientCodec::with_format(Format::Json);
+    let mut server = ServerCodec::with_format(Format::Json);
+    let mut buf = BytesMut::new();
+
+    let username = Username::from("alice").unwrap();
+    client
+        .encode(ClientMessage::Command(Command::WhoIs(username)), &mut buf)
+        .unwrap();
+    match server.decode(&mut buf).unwrap() {
+        Some(ClientMessage::Command(Command::WhoIs(un))) => assert_eq!(un, username),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+
+    let room = RoomInfo {
+        name: RoomName::from("main").unwrap(),
+        unread: 3,
+    };
+    let response = Response::RoomsList(vec![room]);
+    server
+        .encode(ServerMessage::Response(response), &mut buf)
+        .unwrap();
+    match client.decode(&mut buf).unwrap() {
+        Some(ServerMessage::Response(Response::RoomsList(rooms))) => assert_eq!(rooms, [room]),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn json_frame_is_readable_text() {
+    let mut client = ClientCodec::with_format(Format::Json);
+    let mut buf = BytesMut::new();
+    client
+        .encode(ClientMessage::Command(Command::Exit), &mut buf)
+        .unwrap();
+
+    assert_eq!(&buf[..], b"\x00\x00\x00\x12{\"Command\":\"Exit\"}");
+}
+
+#[test]
+fn partial_json_frame_waits_for_body() {
+    let mut server = ServerCodec::with_format(Format::Json);
+    let mut buf = BytesMut::from(&b"\x00\x00\x00\x12{\"Command\""[..]);
+
+    assert!(server.decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b":\"Exit\"}");
+    assert!(matches!(
+        server.decode(&mut buf).unwrap(),
+        Some(ClientMessage::Command(Command::Exit))
+    ));
+}
+
+/// response with 'n' room names, over 64 KiB when 'n' is large
+fn rooms_list(n: usize) -> ServerMessage {
+    let rooms = (0..n)
+        .map(|i| RoomInfo {
+            name: RoomName::from(&format!("room-{:026}", i)).unwrap(),
+            unread: i as u64,
+        })
+        .collect();
+    ServerMessage::Response(Response::RoomsList(rooms))
+}
+
+#[test]
+fn frame_larger_than_64k() {
+    let mut server = ServerCodec::new();
+    let mut client = ClientCodec::new();
+    let mut buf = BytesMut::new();
+
+    server.encode(rooms_list(4096), &mut buf).unwrap();
+    assert!(buf.len() > u16::MAX as usize);
+
+    match client.decode(&mut buf).unwrap() {
+        Some(ServerMessage::Response(Response::RoomsList(rooms))) => assert_eq!(rooms.len(), 4096),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+}
+
+#[test]
+fn encoding_too_large_frame_fails() {
+    for &format in &[Format::Bincode, Format::Json] {
+        let mut server = ServerCodec::with_format(format).max_frame_size(1024);
+        let mut buf = BytesMut::new();
+
+        assert!(matches!(
+            server.encode(rooms_list(64), &mut buf),
+            Err(codec::Error::FrameTooLarge { max: 1024, .. })
+        ));
+        assert!(buf.is_empty());
+    }
+}
+
+#[test]
+fn decoding_too_large_frame_fails_before_body() {
+    let mut server = ServerCodec::with_format(Format::Json).max_frame_size(1024);
+    let mut buf = BytesMut::from(&[0xff, 0xff, 0xff, 0xff][..]);
+
+    assert!(matches!(
+        server.decode(&mut buf),
+        Err(codec::Error::FrameTooLarge {
+            size: 0x7fff_ffff,
+            max: 1024
+        })
+    ));
+    assert!(buf.capacity() < 1024);
+}
+
+#[test]
+fn frame_of_maximum_size_is_accepted() {
+    let mut client = ClientCodec::new();
+    let mut buf = BytesMut::new();
+    client
+        .encode(ClientMessage::Command(Command::Exit), &mut buf)
+        .unwrap();
+
+    let size = buf.len() - 4;
+    let mut server = ServerCodec::with_max_frame_size(size);
+    assert!(matches!(
+        server.decode(&mut buf).unwrap(),
+        Some(ClientMessage::Command(Command::Exit))
+    ));
+}
+
+fn deflate() -> Preface {
+    Preface::new(Format::Bincode, Compression::Deflate)
+}
+
+#[test]
+fn large_frame_is_compressed() {
+    let mut server = ServerCodec::with_preface(deflate());
+    let mut client = ClientCodec::with_preface(deflate());
+    let mut raw = BytesMut::new();
+    let mut buf = BytesMut::new();
+
+    ServerCodec::new()
+        .encode(rooms_list(256), &mut raw)
+        .unwrap();
+    server.encode(rooms_list(256), &mut buf).unwrap();
+    assert!(buf.len() < raw.len() / 2);
+    assert_eq!(buf[0] & 0x80, 0x80);
+
+    match client.decode(&mut buf).unwrap() {
+        Some(ServerMessage::Response(Response::RoomsList(rooms))) => assert_eq!(rooms.len(), 256),
+        msg => panic!("unexpected message: {:?}", msg),
+    }
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn frame_below_threshold_is_sent_raw() {
+    let msg = || ClientMessage::Command(Command::Exit);
+    let mut raw = BytesMut::new();
+    let mut buf = BytesMut::new();
+
+    ClientCodec::new().encode(msg(), &mut raw).unwrap();
+    ClientCodec::with_preface(deflate())
+        .encode(msg(), &mut buf)
+        .unwrap();
+    assert_eq!(buf, raw);
+}
+
+#[test]
+fn compressed_frame_without_compression_fails() {
+    let mut buf = BytesMut::new();
+    ServerCodec::with_preface(deflate())
+        .encode(rooms_list(256), &mut buf)
+        .unwrap();
+
+    assert!(matches!(
+        ClientCodec::new().decode(&mut buf),
+        Err(codec::Error::UnexpectedCompression)
+    ));
+}
+
+#[test]
+fn decompressed_frame_is_limited() {
+    let mut server = ServerCodec::with_preface(deflate());
+    let mut client = ClientCodec::with_preface(deflate()).max_frame_size(1024);
+    let mut buf = BytesMut::new();
+
+    server.encode(rooms_list(256), &mut buf).unwrap();
+    assert!(buf.len() < 1024);
+    assert!(matches!(
+        client.decode(&mut buf),
+        Err(codec::Error::FrameTooLarge { max: 1024, .. })
+    ));
+}
+
+#[test]
+fn body_round_trip() {
+    for &format in &[Format::Bincode, Format::Json] {
+        let client = ClientCodec::with_format(format);
+        let server = ServerCodec::with_format(format);
+
+        let body = client
+            .encode_body(ClientMessage::Command(Command::Exit))
+            .unwrap();
+        assert!(matches!(
+            server.decode_body(&body).unwrap(),
+            ClientMessage::Command(Command::Exit)
+        ));
+    }
+}
+
+#[test]
+fn too_large_body_fails() {
+    let server = ServerCodec::with_format(Format::Json).max_frame_size(1024);
+
+    assert!(matches!(
+        server.encode_body(rooms_list(64)),
+        Err(codec::Error::FrameTooLarge { max: 1024, .. })
+    ));
+    assert!(matches!(
+        server.decode_body(&[b' '; 2048]),
+        Err(codec::Error::FrameTooLarge { max: 1024, .. })
+    ));
+}
@@ -0,0 +1,230 @@
+use arrayvec::{Array, ArrayString};
+use bytes::BytesMut;
+use chrono::{TimeZone, Utc};
+use proptest::prelude::*;
+use rustenger_shared::{
+    account::{Account, Color, Status},
+    codec::{ClientCodec, Compression, Format, Preface, ServerCodec},
+    e2e::EncryptedMessage,
+    message::{
+        AccountMessage, ClientMessage, Command, DirectStatus, Event, ReactionEvent, Receipt,
+        Response, RoomInfo, ServerMessage, SignInError,
+    },
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// strings which fit into 'ArrayString<A>'
+fn array_string<A>(max_chars: usize) -> impl Strategy<Value = ArrayString<A>>
+where
+    A: Array<Item = u8> + Copy,
+{
+    proptest::string::string_regex(&format!("\\PC{{0,{}}}", max_chars))
+        .unwrap()
+        .prop_filter_map("too long", |s| ArrayString::from(&s).ok())
+}
+
+fn preface() -> impl Strategy<Value = Preface> {
+    let format = prop_oneof![Just(Format::Bincode), Just(Format::Json)];
+    let compression = prop_oneof![Just(Compression::None), Just(Compression::Deflate)];
+    (format, compression).prop_map(|(format, compression)| Preface::new(format, compression))
+}
+
+fn color() -> impl Strategy<Value = Color> {
+    prop_oneof![
+        Just(Color::Black),
+        Just(Color::Red),
+        Just(Color::Green),
+        Just(Color::White),
+    ]
+}
+
+fn status() -> impl Strategy<Value = Status> {
+    prop_oneof![
+        Just(Status::Online),
+        Just(Status::Away),
+        Just(Status::Offline)
+    ]
+}
+
+fn account() -> impl Strategy<Value = Account> {
+    (array_string(32), color()).prop_map(|(un, color)| Account::with_color(un, color))
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    prop_oneof![
+        (array_string(32), array_string(32)).prop_map(|(un, pw)| Command::LogIn(un, pw)),
+        (array_string(32), any::<[u8; 32]>()).prop_map(|(un, n)| Command::ScramStart(un, n)),
+        array_string(32).prop_map(Command::CreateRoom),
+        color().prop_map(Command::SelectColor),
+        (any::<u64>(), array_string(16)).prop_map(|(id, r)| Command::React(id, r)),
+        (status(), array_string(64)).prop_map(|(s, m)| Command::SetStatus(s, m)),
+        any::<u32>().prop_map(Command::WhoIsOnline),
+        (array_string(32), any::<u64>()).prop_map(|(rn, id)| Command::MarkRead(rn, id)),
+        any::<u64>().prop_map(Command::MarkDirectRead),
+        Just(Command::Exit),
+    ]
+}
+
+fn client_message() -> impl Strategy<Value = ClientMessage> {
+    prop_oneof![
+        (array_string(256), any::<Option<u64>>())
+            .prop_map(|(text, parent)| ClientMessage::UserMessage(Box::new(text), parent)),
+        command().prop_map(ClientMessage::Command),
+        any::<bool>().prop_map(ClientMessage::Typing),
+        Just(ClientMessage::Ping),
+        any::<u64>().prop_map(ClientMessage::Ack),
+        (
+            array_string(32),
+            any::<[u8; 32]>(),
+            any::<[u8; 12]>(),
+            prop::collection::vec(any::<u8>(), 0..512)
+        )
+            .prop_map(|(un, ephemeral, nonce, ciphertext)| {
+                let payload = EncryptedMessage {
+                    ephemeral,
+                    nonce,
+                    ciphertext,
+                };
+                ClientMessage::Direct(un, payload)
+            }),
+    ]
+}
+
+fn account_message() -> impl Strategy<Value = AccountMessage> {
+    (
+        any::<u64>(),
+        any::<Option<u64>>(),
+        array_string(256),
+        account(),
+        0..4_000_000_000i64,
+        prop::collection::vec((array_string(16), any::<u32>()), 0..8),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(id, parent, text, adresser, secs, reactions, mentioned)| AccountMessage {
+                id,
+                parent,
+                text,
+                adresser,
+                utc: Utc.timestamp(secs, 0),
+                reactions,
+                mentioned,
+            },
+        )
+}
+
+fn server_message() -> impl Strategy<Value = ServerMessage> {
+    prop_oneof![
+        account_message().prop_map(|m| ServerMessage::AccountMessage(Box::new(m))),
+        prop::collection::vec((array_string(32), any::<u64>()), 0..64).prop_map(|rooms| {
+            let rooms = rooms
+                .into_iter()
+                .map(|(name, unread)| RoomInfo { name, unread })
+                .collect();
+            ServerMessage::Response(Response::RoomsList(rooms))
+        }),
+        (any::<u64>(), array_string(32), any::<bool>()).prop_map(|(id, recipient, read)| {
+            let status = if read {
+                DirectStatus::Read
+            } else {
+                DirectStatus::Delivered
+            };
+            ServerMessage::Receipt(Receipt {
+                id,
+                recipient,
+                status,
+            })
+        }),
+        prop::collection::vec(account_message(), 0..8)
+            .prop_map(|thread| ServerMessage::Response(Response::Thread(thread))),
+        any::<u64>().prop_map(|id| ServerMessage::Response(Response::UnknownMessage(id))),
+        array_string(32).prop_map(|un| ServerMessage::Response(Response::UnknownUser(un))),
+        prop_oneof![
+            Just(Ok(())),
+            Just(Err(SignInError::InvalidUserNamePassword)),
+            Just(Err(SignInError::UserNameAlreadyUsed)),
+            Just(Err(SignInError::Banned)),
+        ]
+        .prop_map(|res| ServerMessage::Response(Response::SignInResult(res))),
+        (account(), any::<bool>())
+            .prop_map(|(acc, typing)| ServerMessage::Event(Event::Typing(acc, typing))),
+        account().prop_map(|acc| ServerMessage::Event(Event::Leave(acc))),
+        Just(ServerMessage::Event(Event::Shutdown)),
+        ".{0,64}".prop_map(|text| ServerMessage::Event(Event::Announcement(text))),
+        array_string(32).prop_map(|room| ServerMessage::Event(Event::RoomDeleted(room))),
+        Just(ServerMessage::Pong),
+        ".{0,64}".prop_map(ServerMessage::Motd),
+        (
+            any::<u64>(),
+            array_string(16),
+            account(),
+            any::<bool>(),
+            any::<u32>()
+        )
+            .prop_map(|(id, reaction, adresser, added, count)| {
+                let event = ReactionEvent {
+                    id,
+                    reaction,
+                    adresser,
+                    added,
+                    count,
+                };
+                ServerMessage::Event(Event::Reaction(event))
+            }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn client_message_round_trip(preface in preface(), msgs in prop::collection::vec(client_message(), 1..8)) {
+        let mut client = ClientCodec::with_preface(preface).compression_threshold(16);
+        let mut server = ServerCodec::with_preface(preface).compression_threshold(16);
+        let mut buf = BytesMut::new();
+
+        for msg in &msgs {
+            client.encode(msg.clone(), &mut buf).unwrap();
+        }
+        for msg in &msgs {
+            let decoded = server.decode(&mut buf).unwrap().unwrap();
+            prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+        }
+        prop_assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn server_message_round_trip(preface in preface(), msg in server_message()) {
+        let mut server = ServerCodec::with_preface(preface).compression_threshold(16);
+        let mut client = ClientCodec::with_preface(preface).compression_threshold(16);
+        let mut buf = BytesMut::new();
+
+        server.encode(msg.clone(), &mut buf).unwrap();
+        let decoded = client.decode(&mut buf).unwrap().unwrap();
+        prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+        prop_assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn frame_split_at_any_point(preface in preface(), msg in client_message(), split in any::<prop::sample::Index>()) {
+        let mut client = ClientCodec::with_preface(preface).compression_threshold(16);
+        let mut server = ServerCodec::with_preface(preface).compression_threshold(16);
+        let mut frame = BytesMut::new();
+        client.encode(msg.clone(), &mut frame).unwrap();
+
+        let split = split.index(frame.len());
+        let mut buf = BytesMut::from(&frame[..split]);
+        prop_assert!(server.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(&frame[split..]);
+        let decoded = server.decode(&mut buf).unwrap().unwrap();
+        prop_assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+    }
+
+    #[test]
+    fn arbitrary_bytes_do_not_panic(preface in preface(), bytes in prop::collection::vec(any::<u8>(), 0..2048)) {
+        let mut server = ServerCodec::with_preface(preface).max_frame_size(1024);
+        let mut buf = BytesMut::from(&bytes[..]);
+
+        while let Ok(Some(_)) = server.decode(&mut buf) {}
+        prop_assert!(buf.capacity() <= bytes.len().max(1024 + 4) * 2);
+    }
+}
@@ -0,0 +1,96 @@
+use rustenger_shared::{
+    account::Username,
+    e2e::{self, KeyBundle, KeyPair},
+};
+
+fn username(s: &str) -> Username {
+    Username::from(s).unwrap()
+}
+
+#[test]
+fn recipient_decrypts_message() {
+    let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+
+    let msg = alice
+        .encrypt(username("alice"), username("bob"), &bob.bundle(), b"hello")
+        .unwrap();
+    let plaintext = bob
+        .decrypt(username("alice"), username("bob"), &alice.bundle(), &msg)
+        .unwrap();
+
+    assert_eq!(plaintext, b"hello");
+}
+
+#[test]
+fn other_account_can_not_decrypt() {
+    let (alice, bob, eve) = (
+        KeyPair::generate(),
+        KeyPair::generate(),
+        KeyPair::generate(),
+    );
+
+    let msg = alice
+        .encrypt(username("alice"), username("bob"), &bob.bundle(), b"hello")
+        .unwrap();
+
+    assert!(matches!(
+        eve.decrypt(username("alice"), username("bob"), &alice.bundle(), &msg),
+        Err(e2e::Error::Decrypt)
+    ));
+}
+
+#[test]
+fn tampered_message_is_rejected() {
+    let (alice, bob) = (KeyPair::generate(), KeyPair::generate());
+
+    let mut msg = alice
+        .encrypt(username("alice"), username("bob"), &bob.bundle(), b"hello")
+        .unwrap();
+    msg.ciphertext[0] ^= 1;
+
+    assert!(bob
+        .decrypt(username("alice"), username("bob"), &alice.bundle(), &msg)
+        .is_err());
+}
+
+#[test]
+fn forged_sender_is_rejected() {
+    let (alice, bob, eve) = (
+        KeyPair::generate(),
+        KeyPair::generate(),
+        KeyPair::generate(),
+    );
+
+    // eve claims to be alice, bob uses the keys alice published
+    let msg = eve
+        .encrypt(username("alice"), username("bob"), &bob.bundle(), b"hello")
+        .unwrap();
+
+    assert!(bob
+        .decrypt(username("alice"), username("bob"), &alice.bundle(), &msg)
+        .is_err());
+}
+
+#[test]
+fn low_order_key_is_rejected() {
+    let alice = KeyPair::generate();
+    let bundle = KeyBundle {
+        identity: [0; 32],
+        prekey: [0; 32],
+    };
+
+    assert!(matches!(
+        alice.encrypt(username("alice"), username("bob"), &bundle, b"hello"),
+        Err(e2e::Error::InvalidKey)
+    ));
+}
+
+#[test]
+fn saved_keys_are_restored() {
+    let keys = KeyPair::generate();
+    let (identity, prekey) = keys.to_bytes();
+
+    let restored = KeyPair::from_bytes(identity, prekey);
+    assert_eq!(restored.bundle(), keys.bundle());
+    assert_eq!(restored.bundle().fingerprint().len(), 64);
+}
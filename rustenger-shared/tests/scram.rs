@@ -0,0 +1,101 @@
+use rustenger_shared::{
+    account::{Password, Username},
+    scram::{self, Challenge, ClientExchange, Credentials},
+};
+
+fn username() -> Username {
+    Username::from("alice").unwrap()
+}
+
+fn password(s: &str) -> Password {
+    Password::from(s).unwrap()
+}
+
+#[test]
+fn valid_proof_is_accepted() {
+    let credentials = Credentials::new(&password("secret"));
+    let exchange = ClientExchange::new(username(), password("secret"));
+
+    let challenge = credentials.challenge();
+    let (proof, expected) = exchange.proof(&challenge).unwrap();
+    let signature = credentials
+        .verify_proof(&username(), &exchange.nonce(), &challenge, &proof)
+        .unwrap();
+
+    assert!(ClientExchange::verify_signature(&expected, &signature));
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let credentials = Credentials::new(&password("secret"));
+    let exchange = ClientExchange::new(username(), password("guess"));
+
+    let challenge = credentials.challenge();
+    let (proof, _) = exchange.proof(&challenge).unwrap();
+
+    assert!(credentials
+        .verify_proof(&username(), &exchange.nonce(), &challenge, &proof)
+        .is_none());
+}
+
+#[test]
+fn proof_is_bound_to_challenge() {
+    let credentials = Credentials::new(&password("secret"));
+    let exchange = ClientExchange::new(username(), password("secret"));
+
+    let (proof, _) = exchange.proof(&credentials.challenge()).unwrap();
+
+    // replayed proof does not match the fresh server nonce
+    assert!(credentials
+        .verify_proof(
+            &username(),
+            &exchange.nonce(),
+            &credentials.challenge(),
+            &proof
+        )
+        .is_none());
+}
+
+#[test]
+fn weak_challenge_is_refused() {
+    let credentials = Credentials::with_salt(&password("secret"), [0; 16], scram::ITERATIONS / 2);
+    let exchange = ClientExchange::new(username(), password("secret"));
+
+    assert!(exchange.proof(&credentials.challenge()).is_none());
+}
+
+#[test]
+fn too_hard_challenge_is_refused() {
+    let challenge = Challenge {
+        salt: [0; 16],
+        iterations: scram::MAX_ITERATIONS + 1,
+        server_nonce: scram::nonce(),
+    };
+    let exchange = ClientExchange::new(username(), password("secret"));
+
+    assert!(exchange.proof(&challenge).is_none());
+}
+
+#[test]
+fn plain_password_is_verified() {
+    let credentials = Credentials::new(&password("secret"));
+
+    assert!(credentials.verify_password(&password("secret")));
+    assert!(!credentials.verify_password(&password("guess")));
+}
+
+#[test]
+fn unknown_challenge_is_stable_per_username() {
+    let secret = scram::secret();
+    let bob = Username::from("bob").unwrap();
+
+    let first = scram::unknown_challenge(&secret, &username());
+    let second = scram::unknown_challenge(&secret, &username());
+    assert_eq!(first.salt, second.salt);
+    assert_eq!(first.iterations, scram::ITERATIONS);
+    assert_ne!(first.server_nonce, second.server_nonce);
+
+    assert_ne!(scram::unknown_challenge(&secret, &bob).salt, first.salt);
+    let other = scram::unknown_challenge(&scram::secret(), &username());
+    assert_ne!(other.salt, first.salt);
+}
@@ -0,0 +1,246 @@
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationList,
+    CertificateRevocationListParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyUsagePurpose, RevokedCertParams, SerialNumber,
+};
+use rustenger_shared::tls::{self, RevocationList};
+use rustls::{ClientSession, ServerSession, Session, StreamOwned};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    thread,
+};
+
+/// self-signed certificate for 'localhost' written into PEM files
+struct SelfSigned {
+    dir: PathBuf,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl SelfSigned {
+    fn generate(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("rustenger-tls-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        fs::write(&cert, generated.serialize_pem().unwrap()).unwrap();
+        fs::write(&key, generated.serialize_private_key_pem()).unwrap();
+
+        Self { dir, cert, key }
+    }
+}
+
+/// CA and client certificate with common name 'alice' signed by it
+struct ClientAuth {
+    dir: PathBuf,
+    ca: Certificate,
+    ca_cert: PathBuf,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl ClientAuth {
+    const SERIAL: u8 = 7;
+
+    fn generate(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("rustenger-mtls-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Rustenger CA");
+        let ca = Certificate::from_params(params).unwrap();
+
+        let mut params = CertificateParams::new(Vec::new());
+        params.serial_number = Some(SerialNumber::from_slice(&[Self::SERIAL]));
+        params.distinguished_name.push(DnType::CommonName, "alice");
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client = Certificate::from_params(params).unwrap();
+
+        let ca_cert = dir.join("ca.pem");
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        fs::write(&ca_cert, ca.serialize_pem().unwrap()).unwrap();
+        fs::write(&cert, client.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+        fs::write(&key, client.serialize_private_key_pem()).unwrap();
+
+        Self {
+            dir,
+            ca,
+            ca_cert,
+            cert,
+            key,
+        }
+    }
+
+    /// writes CRL signed by CA which revokes certificate with serial number 'serial'
+    fn revoke(&self, serial: u8) -> PathBuf {
+        let params = CertificateRevocationListParams {
+            this_update: rcgen::date_time_ymd(2020, 1, 1),
+            next_update: rcgen::date_time_ymd(2100, 1, 1),
+            crl_number: SerialNumber::from_slice(&[1]),
+            issuing_distribution_point: None,
+            revoked_certs: vec![RevokedCertParams {
+                serial_number: SerialNumber::from_slice(&[serial]),
+                revocation_time: rcgen::date_time_ymd(2020, 1, 1),
+                reason_code: None,
+                invalidity_date: None,
+            }],
+            alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        let crl = CertificateRevocationList::from_params(params).unwrap();
+
+        let path = self.dir.join(format!("revoked-{}.pem", serial));
+        fs::write(&path, crl.serialize_pem_with_signer(&self.ca).unwrap()).unwrap();
+        path
+    }
+}
+
+impl Drop for ClientAuth {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+impl Drop for SelfSigned {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// runs server which echoes 5 bytes over TLS, returns its port
+fn echo_server(certs: &SelfSigned) -> (u16, thread::JoinHandle<std::io::Result<()>>) {
+    let config = tls::server_config(&certs.cert, &certs.key).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let handle = thread::spawn(move || {
+        let (sock, _) = listener.accept()?;
+        let mut stream = StreamOwned::new(ServerSession::new(&config), sock);
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf)?;
+        stream.write_all(&buf)?;
+        stream.flush()
+    });
+
+    (port, handle)
+}
+
+fn connect(port: u16, ca: &SelfSigned) -> StreamOwned<ClientSession, TcpStream> {
+    let config = tls::client_config(&ca.cert).unwrap();
+    let domain = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let sock = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    StreamOwned::new(ClientSession::new(&config, domain), sock)
+}
+
+#[test]
+fn handshake_with_self_signed_certificate() {
+    let certs = SelfSigned::generate("trusted");
+    let (port, server) = echo_server(&certs);
+
+    let mut stream = connect(port, &certs);
+    stream.write_all(b"hello").unwrap();
+    stream.flush().unwrap();
+
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn untrusted_certificate_is_rejected() {
+    let certs = SelfSigned::generate("server");
+    let other = SelfSigned::generate("other");
+    let (port, server) = echo_server(&certs);
+
+    let mut stream = connect(port, &other);
+    let res = stream
+        .write_all(b"hello")
+        .and_then(|_| stream.flush())
+        .and_then(|_| stream.read_exact(&mut [0; 5]));
+    assert!(res.is_err());
+
+    drop(stream);
+    assert!(server.join().unwrap().is_err());
+}
+
+#[test]
+fn private_key_is_not_certificate() {
+    let certs = SelfSigned::generate("invalid");
+
+    assert!(matches!(
+        tls::load_certs(&certs.key),
+        Err(tls::Error::InvalidCertificate(_))
+    ));
+    assert!(matches!(
+        tls::server_config(&certs.key, &certs.cert),
+        Err(tls::Error::InvalidCertificate(_))
+    ));
+}
+
+#[test]
+fn client_certificate_common_name() {
+    let certs = SelfSigned::generate("mtls-server");
+    let auth = ClientAuth::generate("common-name");
+
+    let config =
+        tls::server_config_with_client_auth(&certs.cert, &certs.key, &auth.ca_cert).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = thread::spawn(move || {
+        let (sock, _) = listener.accept().unwrap();
+        let mut stream = StreamOwned::new(ServerSession::new(&config), sock);
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        let certs = stream.sess.get_peer_certificates().unwrap();
+        tls::common_name(&certs[0])
+    });
+
+    let config = tls::client_config_with_cert(&certs.cert, &auth.cert, &auth.key).unwrap();
+    let domain = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let sock = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut stream = StreamOwned::new(ClientSession::new(&config, domain), sock);
+    stream.write_all(b"hello").unwrap();
+    stream.flush().unwrap();
+
+    assert_eq!(server.join().unwrap().as_deref(), Some("alice"));
+}
+
+#[test]
+fn revoked_client_certificate() {
+    let auth = ClientAuth::generate("revoked");
+    let cert = tls::load_certs(&auth.cert).unwrap().remove(0);
+
+    let revoked = RevocationList::load(&auth.revoke(ClientAuth::SERIAL)).unwrap();
+    assert!(revoked.is_revoked(&cert));
+
+    let revoked = RevocationList::load(&auth.revoke(ClientAuth::SERIAL + 1)).unwrap();
+    assert!(!revoked.is_revoked(&cert));
+}
+
+#[test]
+fn certificate_is_not_revocation_list() {
+    let auth = ClientAuth::generate("invalid-crl");
+
+    assert!(matches!(
+        RevocationList::load(&auth.cert),
+        Err(tls::Error::InvalidRevocationList(_))
+    ));
+}